@@ -1,25 +1,86 @@
-use jni_cli_macro::java_class;
+use jni_cli_macro::{java_class, java_enum};
 use tokenizers as tkz;
 
 struct Tokenizer(tkz::Tokenizer);
 
+// the enums of `tokenizers` can't be annotated, so these mirror them
+
+#[java_enum("dev.gigapixel.tokenizers")]
+enum TruncationStrategy {
+    LongestFirst,
+    OnlyFirst,
+    OnlySecond,
+}
+
+#[java_enum("dev.gigapixel.tokenizers")]
+enum PaddingDirection {
+    Left,
+    Right,
+}
+
+#[java_enum("dev.gigapixel.tokenizers")]
+enum PaddingStrategy {
+    BatchLongest,
+    Fixed(i32),
+}
+
 #[java_class("dev.gigapixel.tokenizers")]
 impl Tokenizer {
-    fn new_from_bytes(bytes: Vec<u8>) -> Tokenizer {
-        let inner = tkz::Tokenizer::from_bytes(bytes).expect("boop");
-        Tokenizer(inner)
+    fn new_from_bytes(bytes: Vec<u8>) -> Result<Tokenizer, tkz::Error> {
+        let inner = tkz::Tokenizer::from_bytes(bytes)?;
+        Ok(Tokenizer(inner))
     }
 
-    fn tokenize(&self, text: String) -> Vec<String> {
-        self.0
-            .encode(text, false)
-            .expect("failed to tokenize")
-            .get_tokens()
-            .to_vec()
+    fn tokenize(&self, text: String) -> Result<Vec<String>, tkz::Error> {
+        Ok(self.0.encode(text, false)?.get_tokens().to_vec())
+    }
+
+    fn set_truncation(
+        &mut self,
+        max_length: i32,
+        strategy: TruncationStrategy,
+    ) -> Result<(), tkz::Error> {
+        let strategy = match strategy {
+            TruncationStrategy::LongestFirst => tkz::TruncationStrategy::LongestFirst,
+            TruncationStrategy::OnlyFirst => tkz::TruncationStrategy::OnlyFirst,
+            TruncationStrategy::OnlySecond => tkz::TruncationStrategy::OnlySecond,
+        };
+        self.0.with_truncation(Some(tkz::TruncationParams {
+            max_length: usize::try_from(max_length)?,
+            strategy,
+            ..Default::default()
+        }))?;
+        Ok(())
+    }
+
+    fn set_padding(
+        &mut self,
+        strategy: PaddingStrategy,
+        direction: PaddingDirection,
+    ) -> Result<(), tkz::Error> {
+        let strategy = match strategy {
+            PaddingStrategy::BatchLongest => tkz::PaddingStrategy::BatchLongest,
+            PaddingStrategy::Fixed(len) => tkz::PaddingStrategy::Fixed(usize::try_from(len)?),
+        };
+        let direction = match direction {
+            PaddingDirection::Left => tkz::PaddingDirection::Left,
+            PaddingDirection::Right => tkz::PaddingDirection::Right,
+        };
+        self.0.with_padding(Some(tkz::PaddingParams {
+            strategy,
+            direction,
+            ..Default::default()
+        }));
+        Ok(())
+    }
+
+    fn padding_direction(&self) -> Option<PaddingDirection> {
+        self.0.get_padding().map(|padding| match padding.direction {
+            tkz::PaddingDirection::Left => PaddingDirection::Left,
+            tkz::PaddingDirection::Right => PaddingDirection::Right,
+        })
     }
 }
 
 #[cfg(test)]
-mod tests {
-    
-}
+mod tests {}
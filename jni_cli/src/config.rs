@@ -0,0 +1,251 @@
+use std::path::PathBuf;
+
+use cargo_metadata::Package;
+use color_eyre::eyre::{self, Context};
+use jni_cli_core::java::JAVA_KEYWORDS;
+use serde::Deserialize;
+
+/// Java toolchain used when neither `jvm-target` nor `--jvm-target` is set
+const DEFAULT_JVM_TARGET: u32 = 19;
+/// Directory the gradle or maven project is generated in, relative to the crate
+const DEFAULT_OUTPUT_DIR: &str = "kotlin";
+
+/// Language of the generated sources
+#[derive(Deserialize, clap::ValueEnum, Clone, Copy, PartialEq, Eq, Debug, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum Lang {
+    #[default]
+    Kotlin,
+    Java,
+}
+
+impl Lang {
+    /// Directory of the sources, relative to the output directory
+    pub fn source_dir(self) -> &'static str {
+        match self {
+            Lang::Kotlin => "src/main/kotlin",
+            Lang::Java => "src/main/java",
+        }
+    }
+
+    /// Extension of the source files
+    pub fn extension(self) -> &'static str {
+        match self {
+            Lang::Kotlin => "kt",
+            Lang::Java => "java",
+        }
+    }
+}
+
+/// Build tool of the project the sources are generated in
+#[derive(Deserialize, clap::ValueEnum, Clone, Copy, PartialEq, Eq, Debug, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum BuildSystem {
+    #[default]
+    Gradle,
+    Maven,
+    /// no build tool, the cli compiles the sources with `javac` or `kotlinc` and writes the jar
+    Direct,
+}
+
+/// The `[package.metadata.jni]` section of the crate's Cargo.toml
+///
+/// ```toml
+/// [package.metadata.jni]
+/// group = "dev.gigapixel"
+/// artifact-id = "tokenizers"
+/// version = "0.1.0"
+/// jvm-target = 17
+/// lang = "kotlin"
+/// build-system = "gradle"
+/// output-dir = "kotlin"
+/// dependencies = ["com.google.guava:guava:32.1.1-jre"]
+/// repository = "https://maven.example.com/releases"
+/// ```
+#[derive(Deserialize, Debug, Default)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct JniMetadata {
+    /// group id of the published artifact, e.g. `org.apache`
+    pub group: Option<String>,
+    /// artifact id of the published artifact, e.g. `commons-io`
+    pub artifact_id: Option<String>,
+    /// version of the published artifact, defaults to the crate version
+    pub version: Option<String>,
+    /// java toolchain version the classes are compiled for
+    pub jvm_target: Option<u32>,
+    /// language of the generated sources, kotlin or java
+    pub lang: Option<Lang>,
+    /// build tool of the generated project, gradle or maven
+    pub build_system: Option<BuildSystem>,
+    /// directory the gradle or maven project is generated in, relative to the crate
+    pub output_dir: Option<PathBuf>,
+    /// extra gradle `implementation` dependencies, e.g. `com.google.guava:guava:32.1.1-jre`
+    #[serde(default)]
+    pub dependencies: Vec<String>,
+    /// maven repository `publish` uploads to, instead of the local maven repository
+    pub repository: Option<String>,
+}
+
+impl JniMetadata {
+    pub fn from_package(package: &Package) -> eyre::Result<JniMetadata> {
+        match package.metadata.get("jni") {
+            Some(jni) => JniMetadata::deserialize(jni)
+                .wrap_err("Invalid [package.metadata.jni] in Cargo.toml"),
+            None => Ok(JniMetadata::default()),
+        }
+    }
+}
+
+/// The configuration of a run, the crate's [`JniMetadata`] overridden by the cli flags
+#[derive(Debug)]
+pub struct Config {
+    pub group: String,
+    pub artifact_id: String,
+    pub version: String,
+    pub jvm_target: u32,
+    pub lang: Lang,
+    pub build_system: BuildSystem,
+    pub output_dir: PathBuf,
+    pub dependencies: Vec<String>,
+    pub repository: Option<String>,
+}
+
+impl Config {
+    pub fn resolve(
+        metadata: JniMetadata,
+        overrides: JniMetadata,
+        crate_name: &str,
+        crate_version: &str,
+    ) -> eyre::Result<Config> {
+        let group = overrides.group.or(metadata.group).ok_or_else(|| {
+            eyre::eyre!("No group set, pass --group or set `group` in [package.metadata.jni]")
+        })?;
+        Ok(Config {
+            group,
+            artifact_id: overrides
+                .artifact_id
+                .or(metadata.artifact_id)
+                .unwrap_or_else(|| crate_name.to_string()),
+            version: overrides
+                .version
+                .or(metadata.version)
+                .unwrap_or_else(|| crate_version.to_string()),
+            jvm_target: overrides
+                .jvm_target
+                .or(metadata.jvm_target)
+                .unwrap_or(DEFAULT_JVM_TARGET),
+            lang: overrides.lang.or(metadata.lang).unwrap_or_default(),
+            build_system: overrides
+                .build_system
+                .or(metadata.build_system)
+                .unwrap_or_default(),
+            output_dir: overrides
+                .output_dir
+                .or(metadata.output_dir)
+                .unwrap_or_else(|| DEFAULT_OUTPUT_DIR.into()),
+            dependencies: metadata
+                .dependencies
+                .into_iter()
+                .chain(overrides.dependencies)
+                .collect(),
+            repository: overrides.repository.or(metadata.repository),
+        })
+    }
+
+    /// Package of the generated `Library` and `NativeLoader`, the group followed by the
+    /// artifact id, made into valid package segments as a crate name like `my-lib` isn't one
+    pub fn project_root(&self) -> String {
+        self.group
+            .split('.')
+            .chain([self.artifact_id.as_str()])
+            .map(package_segment)
+            .collect::<Vec<_>>()
+            .join(".")
+    }
+}
+
+/// Replaces the characters java doesn't allow in a package segment with `_`, prefixing it
+/// with `_` if it starts with a digit and suffixing keywords with `_`
+fn package_segment(name: &str) -> String {
+    let mut segment: String = name
+        .chars()
+        .map(|c| {
+            if c.is_alphanumeric() || c == '_' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect();
+    if segment.is_empty() || segment.starts_with(|c: char| c.is_ascii_digit()) {
+        segment.insert(0, '_');
+    }
+    if segment == "_" || JAVA_KEYWORDS.contains(&segment.as_str()) {
+        segment.push('_');
+    }
+    segment
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Config, JniMetadata, Lang};
+
+    #[test]
+    fn test_cli_overrides_metadata() {
+        let metadata: JniMetadata = serde_json::from_value(serde_json::json!({
+            "group": "dev.gigapixel",
+            "jvm-target": 17,
+            "lang": "java",
+            "dependencies": ["com.google.guava:guava:32.1.1-jre"],
+        }))
+        .expect("valid metadata");
+        let overrides = JniMetadata {
+            artifact_id: Some("tok4j".into()),
+            jvm_target: Some(21),
+            ..JniMetadata::default()
+        };
+        let config = Config::resolve(metadata, overrides, "tokenizers", "0.2.0").expect("valid");
+        assert_eq!(config.group, "dev.gigapixel");
+        assert_eq!(config.artifact_id, "tok4j");
+        assert_eq!(config.version, "0.2.0");
+        assert_eq!(config.jvm_target, 21);
+        assert_eq!(config.lang, Lang::Java);
+        assert_eq!(config.output_dir.to_str(), Some("kotlin"));
+        assert_eq!(config.dependencies, ["com.google.guava:guava:32.1.1-jre"]);
+
+        let missing_group =
+            Config::resolve(JniMetadata::default(), JniMetadata::default(), "a", "0.1.0");
+        assert!(missing_group.is_err());
+        assert!(serde_json::from_value::<JniMetadata>(serde_json::json!({"grop": "a"})).is_err());
+    }
+
+    #[test]
+    fn test_project_root_is_a_valid_package() {
+        let metadata = JniMetadata {
+            group: Some("dev.my-org".into()),
+            ..JniMetadata::default()
+        };
+        let config = Config::resolve(metadata, JniMetadata::default(), "tokenizers-jni", "0.1.0")
+            .expect("valid");
+        assert_eq!(config.artifact_id, "tokenizers-jni");
+        assert_eq!(config.project_root(), "dev.my_org.tokenizers_jni");
+
+        let overrides = JniMetadata {
+            group: Some("dev.gigapixel".into()),
+            artifact_id: Some("2d.native".into()),
+            ..JniMetadata::default()
+        };
+        let config =
+            Config::resolve(JniMetadata::default(), overrides, "a", "0.1.0").expect("valid");
+        assert_eq!(config.project_root(), "dev.gigapixel._2d_native");
+
+        let overrides = JniMetadata {
+            group: Some("dev.gigapixel".into()),
+            artifact_id: Some("native".into()),
+            ..JniMetadata::default()
+        };
+        let config =
+            Config::resolve(JniMetadata::default(), overrides, "a", "0.1.0").expect("valid");
+        assert_eq!(config.project_root(), "dev.gigapixel.native_");
+    }
+}
@@ -0,0 +1,261 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fs,
+    path::{Path, PathBuf},
+};
+
+use cargo_metadata::Package;
+use color_eyre::eyre;
+use jni_cli_core::{
+    java::{
+        class_to_java, enum_to_java, java_exception_classes, java_library_class, java_native_loader,
+    },
+    manifest::Manifest,
+    token_processing::{
+        class_to_kotlin, enum_to_kotlin, exception_classes, fill_lookup, library_object,
+        module_classes, module_enums, native_loader, KotlinClass, PackageLookup,
+    },
+};
+
+use crate::{
+    config::{Config, Lang},
+    modules::CfgSet,
+};
+
+/// First line of every generated file, marks the files `generate` owns and may overwrite or delete
+const GENERATED_HEADER: &str = "// Generated by jni_cli from the rust sources, do not edit\n";
+/// The json [`Manifest`] of the generated classes, relative to the output directory
+const MANIFEST_FILE: &str = "jni-manifest.json";
+
+/// Writes the kotlin or java sources for the `#[java_class]` impl blocks and `#[java_enum]`s
+/// of the crate, deleting generated files whose class no longer exists
+pub fn generate(config: &Config, rust_package: &Package, cfg: &CfgSet) -> eyre::Result<()> {
+    let out = &config.output_dir;
+    let generated = generated_sources(config, rust_package, cfg)?;
+    for path in existing_sources(out)?.keys() {
+        if !generated.contains_key(path) {
+            println!("Removing {}", out.join(path).display());
+            fs::remove_file(out.join(path))?;
+        }
+    }
+    for (path, code) in generated {
+        let path = out.join(path);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(path, code)?;
+    }
+    Ok(())
+}
+
+/// Prints a unified diff between the generated sources on disk and the ones `generate` would
+/// write, without writing anything. Returns whether they're up to date
+pub fn check(config: &Config, rust_package: &Package, cfg: &CfgSet) -> eyre::Result<bool> {
+    let out = &config.output_dir;
+    let generated = generated_sources(config, rust_package, cfg)?;
+    let existing = existing_sources(out)?;
+    let diff = diff_sources(out, &existing, &generated);
+    print!("{diff}");
+    Ok(diff.is_empty())
+}
+
+/// Name of the library the `cdylib` target builds, as passed to `System.loadLibrary`. It is the
+/// `[lib] name` if set, with the hyphens of the package name otherwise replaced by underscores
+fn cdylib_name(package: &Package) -> eyre::Result<String> {
+    let target = package
+        .targets
+        .iter()
+        .find(|target| target.kind.iter().any(|kind| kind == "cdylib"))
+        .ok_or_else(|| {
+            eyre::eyre!(
+                "{} has no cdylib target, is `crate-type = [\"cdylib\"]` set in Cargo.toml?",
+                package.name
+            )
+        })?;
+    Ok(target.name.replace('-', "_"))
+}
+
+/// The files `generate` writes, keyed by their path relative to the output directory
+fn generated_sources(
+    config: &Config,
+    rust_package: &Package,
+    cfg: &CfgSet,
+) -> eyre::Result<BTreeMap<PathBuf, String>> {
+    let rust_lib = &cdylib_name(rust_package)?;
+    let project_root = config.project_root();
+    let modules = crate::modules::load_crate(rust_package, cfg)?;
+
+    // create lookups for structs and enums to package.Class
+    let mut java_class_lookup = PackageLookup::default();
+    for module in &modules {
+        fill_lookup(module, &mut java_class_lookup)?;
+    }
+
+    let mut manifest = Manifest {
+        library: rust_lib.to_string(),
+        classes: vec![],
+        enums: vec![],
+    };
+    for module in &modules {
+        manifest
+            .classes
+            .extend(module_classes(module, &java_class_lookup)?);
+        manifest
+            .enums
+            .extend(module_enums(module, &java_class_lookup)?);
+    }
+
+    let lang = config.lang;
+    let mut classes = manifest
+        .classes
+        .iter()
+        .map(|class| match lang {
+            Lang::Kotlin => class_to_kotlin(&project_root, class, rust_lib),
+            Lang::Java => class_to_java(&project_root, class, rust_lib),
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    // enums have no native methods, and so no exceptions
+    let enums = manifest
+        .enums
+        .iter()
+        .map(|enum_item| match lang {
+            Lang::Kotlin => enum_to_kotlin(enum_item),
+            Lang::Java => enum_to_java(enum_item),
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    // the exceptions thrown by each package's classes and the top-level files
+    let packages: BTreeSet<_> = classes.iter().map(|class| class.path.clone()).collect();
+    match lang {
+        Lang::Kotlin => {
+            classes.extend(packages.iter().flat_map(|p| exception_classes(p)));
+            classes.extend([library_object(&project_root), native_loader(&project_root)]);
+        }
+        Lang::Java => {
+            classes.extend(packages.iter().flat_map(|p| java_exception_classes(p)));
+            classes.extend([
+                java_library_class(&project_root),
+                java_native_loader(&project_root),
+            ]);
+        }
+    }
+
+    let mut files: BTreeMap<_, _> = classes
+        .into_iter()
+        .chain(enums)
+        .map(|KotlinClass { path, name, code }| {
+            let path = Path::new(lang.source_dir())
+                .join(path.replace('.', "/"))
+                .join(format!("{name}.{}", lang.extension()));
+            (path, format!("{GENERATED_HEADER}{code}"))
+        })
+        .collect();
+    let manifest = serde_json::to_string_pretty(&manifest)? + "\n";
+    files.insert(MANIFEST_FILE.into(), manifest);
+    Ok(files)
+}
+
+/// The files in `out` written by an earlier `generate`, the sources are recognized by their
+/// header. Both languages are looked at, so that switching `lang` removes the old sources
+fn existing_sources(out: &Path) -> eyre::Result<BTreeMap<PathBuf, String>> {
+    let mut sources = BTreeMap::new();
+    if out.join(MANIFEST_FILE).exists() {
+        let manifest = fs::read_to_string(out.join(MANIFEST_FILE))?;
+        sources.insert(MANIFEST_FILE.into(), manifest);
+    }
+    for lang in [Lang::Kotlin, Lang::Java] {
+        let source_dir = out.join(lang.source_dir());
+        if !source_dir.exists() {
+            continue;
+        }
+        for file in walkdir::WalkDir::new(&source_dir) {
+            let file = file?;
+            if file
+                .path()
+                .extension()
+                .is_some_and(|ext| ext == lang.extension())
+            {
+                let code = fs::read_to_string(file.path())?;
+                if code.starts_with(GENERATED_HEADER) {
+                    let path = file.path().strip_prefix(out)?.to_path_buf();
+                    sources.insert(path, code);
+                }
+            }
+        }
+    }
+    Ok(sources)
+}
+
+/// Unified diff of every changed, added and removed source, empty when nothing changed
+fn diff_sources(
+    out: &Path,
+    existing: &BTreeMap<PathBuf, String>,
+    generated: &BTreeMap<PathBuf, String>,
+) -> String {
+    let paths: BTreeSet<_> = existing.keys().chain(generated.keys()).collect();
+    let mut diff = String::new();
+    for path in paths {
+        let old = existing.get(path).map(String::as_str).unwrap_or_default();
+        let new = generated.get(path).map(String::as_str).unwrap_or_default();
+        if old == new {
+            continue;
+        }
+        let name = out.join(path).display().to_string();
+        let old_name = if existing.contains_key(path) {
+            format!("a/{name}")
+        } else {
+            "/dev/null".to_string()
+        };
+        let new_name = if generated.contains_key(path) {
+            format!("b/{name}")
+        } else {
+            "/dev/null".to_string()
+        };
+        diff.push_str(
+            &similar::TextDiff::from_lines(old, new)
+                .unified_diff()
+                .context_radius(3)
+                .header(&old_name, &new_name)
+                .to_string(),
+        );
+    }
+    diff
+}
+
+#[cfg(test)]
+mod test {
+    use std::{collections::BTreeMap, path::PathBuf};
+
+    use super::diff_sources;
+
+    #[test]
+    fn test_diff_sources() {
+        let sources = |files: &[(&str, &str)]| -> BTreeMap<PathBuf, String> {
+            files
+                .iter()
+                .map(|(path, code)| (PathBuf::from(path), code.to_string()))
+                .collect()
+        };
+        let existing = sources(&[
+            ("a/Same.kt", "class Same\n"),
+            ("a/Changed.kt", "class Changed\nfun old()\n"),
+            ("a/Removed.kt", "class Removed\n"),
+        ]);
+        let generated = sources(&[
+            ("a/Same.kt", "class Same\n"),
+            ("a/Changed.kt", "class Changed\nfun new()\n"),
+            ("a/Added.kt", "class Added\n"),
+        ]);
+        let dir = PathBuf::from("kotlin");
+        assert_eq!(diff_sources(&dir, &existing, &existing), "");
+
+        let diff = diff_sources(&dir, &existing, &generated);
+        assert!(!diff.contains("Same"));
+        assert!(diff.contains("--- a/kotlin/a/Changed.kt\n+++ b/kotlin/a/Changed.kt\n"));
+        assert!(diff.contains("-fun old()\n+fun new()\n"));
+        assert!(diff.contains("--- /dev/null\n+++ b/kotlin/a/Added.kt\n"));
+        assert!(diff.contains("+class Added\n"));
+        assert!(diff.contains("--- a/kotlin/a/Removed.kt\n+++ /dev/null\n"));
+        assert!(diff.contains("-class Removed\n"));
+    }
+}
@@ -0,0 +1,106 @@
+use std::{fs, path::Path, process::Stdio};
+
+use color_eyre::eyre;
+use tokio::process::Command;
+
+use crate::config::{Config, Lang};
+
+const GRADLE_WRAPPER_TEMPLATE: &[u8] = include_bytes!("template/gradle/wrapper/gradle-wrapper.jar");
+const GRADLE_WRAPPER_PROPERTIES_TEMPLATE: &[u8] =
+    include_bytes!("template/gradle/wrapper/gradle-wrapper.properties");
+const GRADLE_BUILD_TEMPLATE: &str = include_str!("template/build.gradle.kts");
+const GRADLE_SETTINGS_TEMPLATE: &str = include_str!("template/settings.gradle.kts");
+const GRADLE_PROPERTIES_TEMPLATE: &[u8] = include_bytes!("template/gradle.properties");
+const GRADLEW_BAT_TEMPLATE: &[u8] = include_bytes!("template/gradlew.bat");
+const GRADLEW_TEMPLATE: &[u8] = include_bytes!("template/gradlew");
+
+/// Gradle property holding the url of the repository `publish` uploads to
+pub const REPOSITORY_URL_PROPERTY: &str = "jniRepositoryUrl";
+
+/// Scaffolds the gradle project in the output directory. Files that already exist are left
+/// alone, so edits to them survive running `init` again
+pub fn init(config: &Config) -> eyre::Result<()> {
+    let out = &config.output_dir;
+    let reg = handlebars::Handlebars::new();
+    let gradle_settings = reg.render_template(
+        GRADLE_SETTINGS_TEMPLATE,
+        &serde_json::json!({"package_name": config.artifact_id}),
+    )?;
+    let gradle_build = reg.render_template(
+        GRADLE_BUILD_TEMPLATE,
+        &serde_json::json!({
+            "package_name": config.artifact_id,
+            "group_id": config.group,
+            "version": config.version,
+            "jvm_target": config.jvm_target,
+            "kotlin": config.lang == Lang::Kotlin,
+            "dependencies": config.dependencies,
+            "repository_url_property": REPOSITORY_URL_PROPERTY,
+        }),
+    )?;
+    let files: [(&str, &[u8]); 7] = [
+        ("settings.gradle.kts", gradle_settings.as_bytes()),
+        ("build.gradle.kts", gradle_build.as_bytes()),
+        ("gradle.properties", GRADLE_PROPERTIES_TEMPLATE),
+        ("gradle/wrapper/gradle-wrapper.jar", GRADLE_WRAPPER_TEMPLATE),
+        (
+            "gradle/wrapper/gradle-wrapper.properties",
+            GRADLE_WRAPPER_PROPERTIES_TEMPLATE,
+        ),
+        ("gradlew.bat", GRADLEW_BAT_TEMPLATE),
+        ("gradlew", GRADLEW_TEMPLATE),
+    ];
+    for (file, contents) in files {
+        let path = out.join(file);
+        if path.exists() {
+            println!("Keeping {}", path.display());
+            continue;
+        }
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(&path, contents)?;
+        println!("Created {}", path.display());
+    }
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        let gradlew = out.join("gradlew");
+        let mut permissions = fs::metadata(&gradlew)?.permissions();
+        permissions.set_mode(permissions.mode() | 0o111);
+        fs::set_permissions(&gradlew, permissions)?;
+    }
+    fs::create_dir_all(out.join(config.lang.source_dir()))?;
+    fs::create_dir_all(out.join("src/main/resources"))?;
+    Ok(())
+}
+
+/// Runs the gradle wrapper of the project in `out` with `args`
+pub async fn run(out: &Path, args: &[String]) -> eyre::Result<()> {
+    let wrapper = if cfg!(windows) {
+        "gradlew.bat"
+    } else {
+        "gradlew"
+    };
+    let gradlew = out.join(wrapper);
+    if !gradlew.exists() {
+        return Err(eyre::eyre!(
+            "{} doesn't exist, run `cli init` first",
+            gradlew.display()
+        ));
+    }
+    let status = Command::new(gradlew.canonicalize()?)
+        .args(args)
+        .current_dir(out)
+        .stdout(Stdio::inherit())
+        .stderr(Stdio::inherit())
+        .status()
+        .await?;
+    if !status.success() {
+        return Err(eyre::eyre!(
+            "gradle {} failed with {status}",
+            args.join(" ")
+        ));
+    }
+    Ok(())
+}
@@ -0,0 +1,378 @@
+use std::{
+    env,
+    fs::{self, File},
+    io::Write,
+    path::{Path, PathBuf},
+    process::Stdio,
+};
+
+use color_eyre::eyre;
+use md5::Md5;
+use sha1::Sha1;
+use sha2::{Digest, Sha256, Sha512};
+use tokio::process::Command;
+use zip::{write::FileOptions, CompressionMethod, ZipWriter};
+
+use crate::{
+    config::{Config, Lang},
+    maven,
+};
+
+const ARTIFACT_POM_TEMPLATE: &str = include_str!("template/artifact.pom");
+
+/// The kotlin standard library the classes compiled by `kotlinc` depend on
+const KOTLIN_STDLIB: &str = "org.jetbrains.kotlin:kotlin-stdlib:1.9.0";
+/// Directory the classes are compiled to, relative to the output directory
+const CLASSES_DIR: &str = "build/classes";
+/// Directory the jar, pom and gradle module are written to, relative to the output directory
+const LIBS_DIR: &str = "build/libs";
+const RESOURCES_DIR: &str = "src/main/resources";
+
+/// Creates the directories of the sources and the native libraries. Without a build tool there
+/// is nothing else to scaffold
+pub fn init(config: &Config) -> eyre::Result<()> {
+    fs::create_dir_all(config.output_dir.join(config.lang.source_dir()))?;
+    fs::create_dir_all(config.output_dir.join(RESOURCES_DIR))?;
+    Ok(())
+}
+
+/// Compiles the sources with `javac` or `kotlinc`, then writes the jar with the classes and
+/// resources next to its pom and gradle module metadata
+pub async fn build(config: &Config) -> eyre::Result<()> {
+    let out = &config.output_dir;
+    let classes = out.join(CLASSES_DIR);
+    if classes.exists() {
+        fs::remove_dir_all(&classes)?;
+    }
+    fs::create_dir_all(&classes)?;
+    compile(config, &classes).await?;
+
+    let libs = out.join(LIBS_DIR);
+    fs::create_dir_all(&libs)?;
+    let base_name = format!("{}-{}", config.artifact_id, config.version);
+    let pom = render_pom(config)?;
+    let jar = libs.join(format!("{base_name}.jar"));
+    write_jar(config, &jar, &pom, &[&classes, &out.join(RESOURCES_DIR)])?;
+    fs::write(libs.join(format!("{base_name}.pom")), &pom)?;
+    let module = gradle_module(config, &jar)?;
+    fs::write(libs.join(format!("{base_name}.module")), module)?;
+    println!("Created {}", jar.display());
+    Ok(())
+}
+
+/// Copies the jar, pom and gradle module written by [`build`] into the local maven repository,
+/// or into the configured repository if it is a `file:` url
+pub fn publish(config: &Config) -> eyre::Result<()> {
+    let repository = match &config.repository {
+        Some(repository) => repository
+            .strip_prefix("file://")
+            .map(PathBuf::from)
+            .ok_or_else(|| {
+                eyre::eyre!(
+                    "Only file:// repositories can be published to without a build system, \
+                     use --build-system gradle or maven for {repository}"
+                )
+            })?,
+        None => home_dir()?.join(".m2/repository"),
+    };
+    let dir = repository
+        .join(config.group.replace('.', "/"))
+        .join(&config.artifact_id)
+        .join(&config.version);
+    fs::create_dir_all(&dir)?;
+    let base_name = format!("{}-{}", config.artifact_id, config.version);
+    for extension in ["jar", "pom", "module"] {
+        let file = format!("{base_name}.{extension}");
+        let built = config.output_dir.join(LIBS_DIR).join(&file);
+        if !built.exists() {
+            return Err(eyre::eyre!(
+                "{} doesn't exist, run `cli build` first",
+                built.display()
+            ));
+        }
+        fs::copy(&built, dir.join(&file))?;
+        println!("Published {}", dir.join(&file).display());
+    }
+    Ok(())
+}
+
+fn home_dir() -> eyre::Result<PathBuf> {
+    env::var_os("HOME")
+        .or_else(|| env::var_os("USERPROFILE"))
+        .map(PathBuf::from)
+        .ok_or_else(|| eyre::eyre!("Neither HOME nor USERPROFILE is set"))
+}
+
+/// Finds `tool` in `$home_var/bin` or else on the `PATH`
+fn find_tool(tool: &str, home_var: &str) -> eyre::Result<PathBuf> {
+    let file_name = if cfg!(windows) {
+        format!("{tool}.exe")
+    } else {
+        tool.to_string()
+    };
+    let home = env::var_os(home_var).map(|home| PathBuf::from(home).join("bin"));
+    let path = env::var_os("PATH");
+    let dirs = home
+        .into_iter()
+        .chain(path.iter().flat_map(env::split_paths));
+    for dir in dirs {
+        if dir.join(&file_name).is_file() {
+            return Ok(dir.join(&file_name));
+        }
+    }
+    Err(eyre::eyre!(
+        "{tool} wasn't found in ${home_var}/bin or on the PATH"
+    ))
+}
+
+/// The source files of the language under `dir`, sorted so that the build is reproducible
+fn source_files(dir: &Path, lang: Lang) -> eyre::Result<Vec<PathBuf>> {
+    let mut files = vec![];
+    for file in walkdir::WalkDir::new(dir).sort_by_file_name() {
+        let file = file?;
+        if file
+            .path()
+            .extension()
+            .is_some_and(|ext| ext == lang.extension())
+        {
+            files.push(file.into_path());
+        }
+    }
+    Ok(files)
+}
+
+async fn compile(config: &Config, classes: &Path) -> eyre::Result<()> {
+    let sources = source_files(
+        &config.output_dir.join(config.lang.source_dir()),
+        config.lang,
+    )?;
+    if sources.is_empty() {
+        return Err(eyre::eyre!(
+            "No sources in {}, run `cli generate` first",
+            config.output_dir.join(config.lang.source_dir()).display()
+        ));
+    }
+    let mut command = match config.lang {
+        Lang::Java => {
+            let mut command = Command::new(find_tool("javac", "JAVA_HOME")?);
+            command.args(["--release", &config.jvm_target.to_string()]);
+            command
+        }
+        Lang::Kotlin => {
+            let mut command = Command::new(find_tool("kotlinc", "KOTLIN_HOME")?);
+            command.args(["-jvm-target", &config.jvm_target.to_string()]);
+            command
+        }
+    };
+    let status = command
+        .arg("-d")
+        .arg(classes)
+        .args(&sources)
+        .stdout(Stdio::inherit())
+        .stderr(Stdio::inherit())
+        .status()
+        .await?;
+    if !status.success() {
+        return Err(eyre::eyre!("Compiling the sources failed with {status}"));
+    }
+    Ok(())
+}
+
+/// The dependencies of the published artifact, including the kotlin standard library when the
+/// sources are kotlin
+fn dependencies(config: &Config) -> eyre::Result<Vec<serde_json::Value>> {
+    let kotlin_stdlib = (config.lang == Lang::Kotlin).then(|| KOTLIN_STDLIB.to_string());
+    let dependencies: Vec<_> = kotlin_stdlib
+        .into_iter()
+        .chain(config.dependencies.iter().cloned())
+        .collect();
+    maven::dependency_coordinates(&dependencies)
+}
+
+fn render_pom(config: &Config) -> eyre::Result<String> {
+    let reg = handlebars::Handlebars::new();
+    Ok(reg.render_template(
+        ARTIFACT_POM_TEMPLATE,
+        &serde_json::json!({
+            "package_name": config.artifact_id,
+            "group_id": config.group,
+            "version": config.version,
+            "dependencies": dependencies(config)?,
+        }),
+    )?)
+}
+
+/// Writes the jar with its manifest, the pom under `META-INF/maven` like maven does, and the
+/// files of every directory in `roots`
+fn write_jar(config: &Config, jar: &Path, pom: &str, roots: &[&Path]) -> eyre::Result<()> {
+    let mut zip = ZipWriter::new(File::create(jar)?);
+    let options = FileOptions::default().compression_method(CompressionMethod::Deflated);
+
+    zip.start_file("META-INF/MANIFEST.MF", options)?;
+    zip.write_all(
+        format!(
+            "Manifest-Version: 1.0\r\nCreated-By: jni_cli {}\r\nImplementation-Title: {}\r\nImplementation-Version: {}\r\n\r\n",
+            env!("CARGO_PKG_VERSION"),
+            config.artifact_id,
+            config.version
+        )
+        .as_bytes(),
+    )?;
+    let maven_dir = format!("META-INF/maven/{}/{}", config.group, config.artifact_id);
+    zip.start_file(format!("{maven_dir}/pom.xml"), options)?;
+    zip.write_all(pom.as_bytes())?;
+    zip.start_file(format!("{maven_dir}/pom.properties"), options)?;
+    zip.write_all(
+        format!(
+            "groupId={}\nartifactId={}\nversion={}\n",
+            config.group, config.artifact_id, config.version
+        )
+        .as_bytes(),
+    )?;
+
+    for root in roots.iter().filter(|root| root.exists()) {
+        for file in walkdir::WalkDir::new(root).min_depth(1).sort_by_file_name() {
+            let file = file?;
+            let name = file
+                .path()
+                .strip_prefix(root)?
+                .components()
+                .map(|component| component.as_os_str().to_string_lossy())
+                .collect::<Vec<_>>()
+                .join("/");
+            if file.file_type().is_dir() {
+                zip.add_directory(name, options)?;
+            } else {
+                zip.start_file(name, options)?;
+                zip.write_all(&fs::read(file.path())?)?;
+            }
+        }
+    }
+    zip.finish()?;
+    Ok(())
+}
+
+/// The gradle module metadata of the artifact, which gradle prefers over the pom and which
+/// records the JVM version the classes need
+fn gradle_module(config: &Config, jar: &Path) -> eyre::Result<String> {
+    let bytes = fs::read(jar)?;
+    let file_name = jar
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let file = serde_json::json!({
+        "name": file_name,
+        "url": file_name,
+        "size": bytes.len(),
+        "sha512": hex(&Sha512::digest(&bytes)),
+        "sha256": hex(&Sha256::digest(&bytes)),
+        "sha1": hex(&Sha1::digest(&bytes)),
+        "md5": hex(&Md5::digest(&bytes)),
+    });
+    let dependencies: Vec<_> = dependencies(config)?
+        .into_iter()
+        .map(|dependency| {
+            serde_json::json!({
+                "group": dependency["group_id"],
+                "module": dependency["artifact_id"],
+                "version": { "requires": dependency["version"] },
+            })
+        })
+        .collect();
+    let variant = |name: &str, usage: &str| {
+        serde_json::json!({
+            "name": name,
+            "attributes": {
+                "org.gradle.category": "library",
+                "org.gradle.dependency.bundling": "external",
+                "org.gradle.jvm.version": config.jvm_target,
+                "org.gradle.libraryelements": "jar",
+                "org.gradle.usage": usage,
+            },
+            "dependencies": dependencies,
+            "files": [file],
+        })
+    };
+    let module = serde_json::json!({
+        "formatVersion": "1.1",
+        "component": {
+            "group": config.group,
+            "module": config.artifact_id,
+            "version": config.version,
+            "attributes": { "org.gradle.status": "release" },
+        },
+        "createdBy": {
+            "jni_cli": { "version": env!("CARGO_PKG_VERSION") },
+        },
+        "variants": [
+            variant("apiElements", "java-api"),
+            variant("runtimeElements", "java-runtime"),
+        ],
+    });
+    Ok(serde_json::to_string_pretty(&module)? + "\n")
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Read;
+
+    use crate::config::{Config, JniMetadata, Lang};
+
+    use super::{gradle_module, render_pom, write_jar};
+
+    #[test]
+    fn test_write_jar() {
+        let dir = tempfile::tempdir().expect("tempdir");
+        let classes = dir.path().join("classes");
+        std::fs::create_dir_all(classes.join("dev/gigapixel")).expect("created");
+        std::fs::write(classes.join("dev/gigapixel/Tokenizer.class"), b"class").expect("written");
+        let resources = dir.path().join("resources");
+        std::fs::create_dir_all(resources.join("natives/linux-x86_64")).expect("created");
+        std::fs::write(resources.join("natives/linux-x86_64/libtok.so"), b"elf").expect("written");
+
+        let metadata = JniMetadata {
+            group: Some("dev.gigapixel".into()),
+            lang: Some(Lang::Java),
+            dependencies: vec!["com.google.guava:guava:32.1.1-jre".into()],
+            ..JniMetadata::default()
+        };
+        let config =
+            Config::resolve(metadata, JniMetadata::default(), "tokenizers", "0.2.0").expect("ok");
+        let pom = render_pom(&config).expect("pom");
+        assert!(pom.contains("<artifactId>guava</artifactId>"));
+        assert!(!pom.contains("kotlin"));
+
+        let jar = dir.path().join("tokenizers-0.2.0.jar");
+        write_jar(&config, &jar, &pom, &[&classes, &resources]).expect("jar");
+        let mut zip = zip::ZipArchive::new(std::fs::File::open(&jar).expect("jar")).expect("zip");
+        assert_eq!(
+            zip.by_index(0).expect("entry").name(),
+            "META-INF/MANIFEST.MF"
+        );
+        let mut native = String::new();
+        zip.by_name("natives/linux-x86_64/libtok.so")
+            .expect("native")
+            .read_to_string(&mut native)
+            .expect("read");
+        assert_eq!(native, "elf");
+        assert!(zip.by_name("dev/gigapixel/Tokenizer.class").is_ok());
+        assert!(zip
+            .by_name("META-INF/maven/dev.gigapixel/tokenizers/pom.xml")
+            .is_ok());
+
+        let module: serde_json::Value =
+            serde_json::from_str(&gradle_module(&config, &jar).expect("module")).expect("json");
+        let variant = &module["variants"][1];
+        assert_eq!(variant["attributes"]["org.gradle.jvm.version"], 19);
+        assert_eq!(variant["dependencies"][0]["module"], "guava");
+        assert_eq!(variant["files"][0]["name"], "tokenizers-0.2.0.jar");
+        assert_eq!(
+            variant["files"][0]["size"],
+            std::fs::metadata(&jar).expect("jar").len()
+        );
+    }
+}
@@ -1,36 +1,152 @@
-use std::{collections::HashMap, fs, path::Path, process::Stdio};
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs,
+    path::PathBuf,
+};
 
-use cargo_metadata::MetadataCommand;
+use cargo_metadata::{MetadataCommand, Package};
+use clap::{Args, Parser, Subcommand};
 use color_eyre::eyre::{self};
-use jni_cli_core::token_processing::{fill_lookup, KotlinClass, PackageLookup};
-
-const GRADLE_WRAPPER_TEMPLATE: &[u8] = include_bytes!("template/gradle/wrapper/gradle-wrapper.jar");
-const GRADLE_WRAPPER_PROPERTIES_TEMPLATE: &[u8] =
-    include_bytes!("template/gradle/wrapper/gradle-wrapper.properties");
-const GRADLE_BUILD_TEMPLATE: &str = include_str!("template/build.gradle.kts");
-const GRADLE_SETTINGS_TEMPLATE: &str = include_str!("template/settings.gradle.kts");
-const GRADLE_PROPERTIES_TEMPLATE: &[u8] = include_bytes!("template/gradle.properties");
-const GRADLEW_BAT_TEMPLATE: &[u8] = include_bytes!("template/gradlew.bat");
-const GRADLEW_TEMPLATE: &[u8] = include_bytes!("template/gradlew");
-use clap::Parser;
-use tokio::process::Command;
-
-/// Simple program to greet a person
+
+mod config;
+mod generate;
+mod gradle;
+mod jar;
+mod maven;
+mod modules;
+mod native;
+
+use config::{BuildSystem, Config, JniMetadata, Lang};
+
+/// Generates a kotlin library around a rust cdylib annotated with `#[java_class]`
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
-struct Args {
-    /// Name of the group e.g. org.apache
+struct Cli {
+    #[command(subcommand)]
+    command: CliCommand,
+}
+
+#[derive(Subcommand, Debug)]
+enum CliCommand {
+    /// Scaffolds the gradle or maven project, leaving files that already exist untouched
+    Init(ConfigArgs),
+    /// Regenerates the kotlin or java sources from the `#[java_class]` impl blocks and
+    /// `#[java_enum]`s
+    Generate {
+        #[command(flatten)]
+        config: ConfigArgs,
+        #[command(flatten)]
+        features: FeatureArgs,
+        /// Only print a diff of the sources that would change, failing if there are any
+        #[arg(long)]
+        check: bool,
+        /// Rust target triple whose `#[cfg]` the sources follow, may be repeated. Items enabled
+        /// for only some of them are rejected. Defaults to the host
+        #[arg(short, long = "target")]
+        targets: Vec<String>,
+    },
+    /// Compiles the rust library for every target and runs the gradle or maven build to produce the jar
+    Build {
+        #[command(flatten)]
+        config: ConfigArgs,
+        #[command(flatten)]
+        features: FeatureArgs,
+        #[command(flatten)]
+        targets: TargetArgs,
+    },
+    /// Publishes the jar to the configured maven repository, or the local one if there is none
+    Publish(ConfigArgs),
+}
+
+/// Flags overriding the `[package.metadata.jni]` section of Cargo.toml
+#[derive(Args, Debug)]
+struct ConfigArgs {
+    /// Name of the group e.g. org.apache. Overrides `group` in [package.metadata.jni]
+    #[arg(short, long)]
+    group: Option<String>,
+    /// Name of the package e.g. commons-io. Overrides `artifact-id`, defaults to the crate name
     #[arg(short, long)]
-    group: String,
-    /// Name of the package e.g. commons-io
+    package: Option<String>,
+    /// Version of the published artifact. Overrides `version`, defaults to the crate version
+    #[arg(long)]
+    artifact_version: Option<String>,
+    /// Java toolchain version to compile for. Overrides `jvm-target`
+    #[arg(long)]
+    jvm_target: Option<u32>,
+    /// Language of the generated sources. Overrides `lang`, defaults to kotlin
+    #[arg(long, value_enum)]
+    lang: Option<Lang>,
+    /// Build tool of the generated project, or direct to compile and write the jar without one.
+    /// Overrides `build-system`, defaults to gradle
+    #[arg(long, value_enum)]
+    build_system: Option<BuildSystem>,
+    /// Directory to generate the project in. Overrides `output-dir`, defaults to kotlin
     #[arg(short, long)]
-    package: String,
+    output_dir: Option<PathBuf>,
+    /// Extra gradle dependency e.g. com.google.guava:guava:32.1.1-jre, may be repeated. Added to
+    /// `dependencies`
+    #[arg(long = "dependency")]
+    dependencies: Vec<String>,
+    /// Maven repository url to publish to. Overrides `repository`
+    #[arg(long)]
+    repository: Option<String>,
+}
+
+/// Flags selecting the crate features, like cargo's
+#[derive(Args, Debug)]
+struct FeatureArgs {
+    /// Features of the crate to enable, comma separated. May be repeated
+    #[arg(short = 'F', long, value_delimiter = ',')]
+    features: Vec<String>,
+    /// Enables every feature of the crate
+    #[arg(long)]
+    all_features: bool,
+    /// Leaves out the `default` feature of the crate
+    #[arg(long)]
+    no_default_features: bool,
+}
+
+impl FeatureArgs {
+    /// The flags passed on to `cargo build`
+    fn cargo_args(&self) -> Vec<String> {
+        let mut args = vec![];
+        if !self.features.is_empty() {
+            args.push(format!("--features={}", self.features.join(",")));
+        }
+        if self.all_features {
+            args.push("--all-features".to_string());
+        }
+        if self.no_default_features {
+            args.push("--no-default-features".to_string());
+        }
+        args
+    }
+}
+
+#[derive(Args, Debug)]
+struct TargetArgs {
+    /// Rust target triple to build and bundle into the jar, may be repeated. Defaults to the host
+    #[arg(short, long = "target")]
+    targets: Vec<String>,
+    /// Command building the rust library, e.g. "cargo zigbuild" for cross compiling with zig
+    #[arg(long, default_value = "cargo")]
+    cargo: String,
+    /// Linker to use for a target, as TRIPLE=LINKER. May be repeated
+    #[arg(long = "linker", value_parser = parse_linker)]
+    linkers: Vec<(String, String)>,
+}
+
+fn parse_linker(linker: &str) -> Result<(String, String), String> {
+    linker
+        .split_once('=')
+        .map(|(triple, linker)| (triple.to_string(), linker.to_string()))
+        .ok_or_else(|| format!("Expected TRIPLE=LINKER, got {linker}"))
 }
 
 #[tokio::main]
 async fn main() -> color_eyre::Result<()> {
     color_eyre::install()?;
-    let Args { group, package } = Args::parse();
+    let Cli { command } = Cli::parse();
 
     let mut cmd = MetadataCommand::new();
     cmd.manifest_path("Cargo.toml");
@@ -38,127 +154,176 @@ async fn main() -> color_eyre::Result<()> {
     let rust_package = metadata
         .root_package()
         .ok_or_else(|| eyre::eyre!("No package found"))?;
-    let rust_lib = rust_package.name.clone();
-    let reg = handlebars::Handlebars::new();
-    let package_name = package.as_str();
-    let project_root = format!("{group}.{package_name}");
-    let group_dir = group.replace('.', "/");
-    fs::create_dir_all(format!("kotlin/src/main/kotlin/{group_dir}/{package_name}"))?;
-    fs::create_dir_all("kotlin/src/main/resources")?;
-    fs::create_dir_all("kotlin/gradle/wrapper")?;
-    let gradle_settings = reg.render_template(
-        GRADLE_SETTINGS_TEMPLATE,
-        &serde_json::json!({"package_name": package_name}),
-    )?;
-    fs::write("kotlin/settings.gradle.kts", gradle_settings)?;
-    fs::write(
-        "kotlin/gradle/wrapper/gradle-wrapper.jar",
-        GRADLE_WRAPPER_TEMPLATE,
-    )?;
-    fs::write(
-        "kotlin/gradle/wrapper/gradle-wrapper.properties",
-        GRADLE_WRAPPER_PROPERTIES_TEMPLATE,
-    )?;
-    let gradle_build = reg.render_template(
-        GRADLE_BUILD_TEMPLATE,
-        &serde_json::json!({"package_name": package_name, "group_id": group}),
-    )?;
-    fs::write("kotlin/build.gradle.kts", gradle_build)?;
-
-    fs::write("kotlin/gradle.properties", GRADLE_PROPERTIES_TEMPLATE)?;
-
-    fs::write("kotlin/gradlew.bat", GRADLEW_BAT_TEMPLATE)?;
-    fs::write("kotlin/gradlew", GRADLEW_TEMPLATE)?;
-
-    let mut java_class_lookup: PackageLookup = HashMap::new();
-
-    // create lookups for structs to package.Class
-    for file in walkdir::WalkDir::new("src") {
-        let file = file?;
-        let name = file.file_name();
-        let name_str = name
-            .to_str()
-            .ok_or(color_eyre::eyre::eyre!("Failed to convert filename to str"))?;
-        let end = name_str
-            .chars()
-            .skip(name_str.len() - 3)
-            .collect::<String>();
-        if &end == ".rs" {
-            let path = file.path();
-            println!("{path:?}");
-            let rust = fs::read_to_string(file.path())?;
-            fill_lookup(&rust, &mut java_class_lookup)?;
-        }
-    }
-
-    // create Kotlin files
-    for file in walkdir::WalkDir::new("src") {
-        let file = file?;
-        let name = file.file_name();
-        let name_str = name
-            .to_str()
-            .ok_or(color_eyre::eyre::eyre!("Failed to convert filename to str"))?;
-        let end = name_str
-            .chars()
-            .skip(name_str.len() - 3)
-            .collect::<String>();
-        if &end == ".rs" {
-            let path = file.path();
-            println!("{path:?}");
-            let rust = fs::read_to_string(file.path())?;
-            let impls = jni_cli_core::token_processing::rust_file_to_tokens(
-                &project_root,
-                &rust,
-                &java_class_lookup,
-                &rust_lib,
-            )?;
-            for KotlinClass { path, name, code } in impls {
-                let path = path.replace('.', "/");
-                let file_dir = format!("kotlin/src/main/kotlin/{path}");
-                fs::create_dir_all(&file_dir)?;
-                fs::write(format!("{file_dir}/{name}.kt"), code)?;
+
+    match command {
+        CliCommand::Init(config) => {
+            let config = resolve_config(config, rust_package)?;
+            match config.build_system {
+                BuildSystem::Gradle => gradle::init(&config),
+                BuildSystem::Maven => maven::init(&config),
+                BuildSystem::Direct => jar::init(&config),
+            }
+        }
+        CliCommand::Generate {
+            config,
+            features,
+            check,
+            targets,
+        } => {
+            let config = resolve_config(config, rust_package)?;
+            let features = modules::enabled_features(
+                rust_package,
+                &features.features,
+                features.all_features,
+                features.no_default_features,
+            );
+            let cfg = modules::CfgSet::for_targets(features, &targets)?;
+            if !check {
+                return generate::generate(&config, rust_package, &cfg);
+            }
+            if !generate::check(&config, rust_package, &cfg)? {
+                std::process::exit(1);
             }
+            Ok(())
         }
+        CliCommand::Build {
+            config,
+            features,
+            targets,
+        } => {
+            build(
+                &resolve_config(config, rust_package)?,
+                &features,
+                targets,
+                rust_package,
+            )
+            .await
+        }
+        CliCommand::Publish(config) => publish(&resolve_config(config, rust_package)?).await,
     }
+}
 
-    // create top-level file
-    fs::write(
-        format!(
-            "kotlin/src/main/kotlin/{}/Library.kt",
-            project_root.replace('.', "/")
-        ),
-        format!(
-            r#"
-package {project_root}
-
-
-object Library {{
-    val CLEANER = 
-        java.lang.ref.Cleaner.create()
-}}
-               "#,
-        ),
-    )?;
-
-    // compile rust artifact
-    let path = Path::new("cargo");
-    let args = ["build", "--release"];
-    Command::new(path)
-        .args(args)
-        .stdout(Stdio::inherit())
-        .stderr(Stdio::inherit())
-        .spawn()?
-        .wait()
-        .await?;
-
-    // move rust-artifact
-    println!("Name: lib{rust_lib}.dylib");
-    fs::copy(
-        // todo change to be the actual location
-        format!("../target/release/lib{rust_lib}.dylib"),
-        format!("kotlin/src/main/resources/lib{rust_lib}.dylib"),
-    )?;
-    Ok(())
+fn resolve_config(args: ConfigArgs, rust_package: &Package) -> eyre::Result<Config> {
+    let ConfigArgs {
+        group,
+        package,
+        artifact_version,
+        jvm_target,
+        lang,
+        build_system,
+        output_dir,
+        dependencies,
+        repository,
+    } = args;
+    let overrides = JniMetadata {
+        group,
+        artifact_id: package,
+        version: artifact_version,
+        jvm_target,
+        lang,
+        build_system,
+        output_dir,
+        dependencies,
+        repository,
+    };
+    Config::resolve(
+        JniMetadata::from_package(rust_package)?,
+        overrides,
+        &rust_package.name,
+        &rust_package.version.to_string(),
+    )
+}
+
+/// Compiles the rust library for every target into `natives/<os>-<arch>`, then builds the jar
+async fn build(
+    config: &Config,
+    features: &FeatureArgs,
+    targets: TargetArgs,
+    rust_package: &Package,
+) -> eyre::Result<()> {
+    let TargetArgs {
+        targets,
+        cargo,
+        linkers,
+    } = targets;
+    let linkers: HashMap<String, String> = linkers.into_iter().collect();
+    // the platforms to bundle, each built for a target triple or the host
+    let mut platforms = BTreeMap::new();
+    for target in &targets {
+        let platform = native::platform_for_triple(target)?;
+        if let Some(other) = platforms.insert(platform.clone(), Some(target.as_str())) {
+            return Err(eyre::eyre!(
+                "Targets {} and {target} both build for {platform}",
+                other.unwrap_or_default()
+            ));
+        }
+    }
+    if platforms.is_empty() {
+        platforms.insert(native::host_platform(), None);
+    }
+
+    // libraries of an earlier build for other platforms would be bundled as well
+    let natives_dir = config.output_dir.join("src/main/resources/natives");
+    if natives_dir.exists() {
+        fs::remove_dir_all(&natives_dir)?;
+    }
+
+    // compile rust artifacts and move them to natives/<os>-<arch>
+    for (platform, target) in &platforms {
+        let linker = target
+            .and_then(|target| linkers.get(target))
+            .map(String::as_str);
+        let cargo_args = features.cargo_args();
+        let cdylib =
+            native::build_cdylib(&cargo, &cargo_args, *target, linker, &rust_package.id).await?;
+        let file_name = cdylib
+            .file_name()
+            .ok_or_else(|| eyre::eyre!("cdylib {cdylib} has no file name"))?;
+        let resource_dir = natives_dir.join(platform);
+        fs::create_dir_all(&resource_dir)?;
+        let resource = resource_dir.join(file_name);
+        println!("Name: {}", resource.display());
+        fs::copy(&cdylib, resource)?;
+    }
+    // read by the generated NativeLoader to tell which platforms are supported
+    let bundled = platforms.keys().map(|platform| format!("{platform}\n"));
+    fs::write(natives_dir.join("platforms"), bundled.collect::<String>())?;
+
+    match config.build_system {
+        BuildSystem::Gradle => gradle::run(&config.output_dir, &["build".to_string()]).await,
+        BuildSystem::Maven => maven::run(&config.output_dir, &["package".to_string()]).await,
+        BuildSystem::Direct => jar::build(config).await,
+    }
+}
+
+/// Publishes the jar built by `build` with gradle or maven, or copies it into a maven repository
+async fn publish(config: &Config) -> eyre::Result<()> {
+    match config.build_system {
+        BuildSystem::Gradle => {
+            let args = match &config.repository {
+                Some(repository) => vec![
+                    "publishMavenPublicationToConfiguredRepository".to_string(),
+                    format!("-P{}={repository}", gradle::REPOSITORY_URL_PROPERTY),
+                ],
+                None => vec!["publishToMavenLocal".to_string()],
+            };
+            gradle::run(&config.output_dir, &args).await
+        }
+        BuildSystem::Maven => {
+            let args = match &config.repository {
+                Some(repository) => vec![
+                    "deploy".to_string(),
+                    format!(
+                        "-DaltDeploymentRepository={}::{repository}",
+                        maven::REPOSITORY_ID
+                    ),
+                ],
+                None => vec!["install".to_string()],
+            };
+            maven::run(&config.output_dir, &args).await
+        }
+        BuildSystem::Direct => jar::publish(config),
+    }
 }
 
 #[cfg(test)]
@@ -166,13 +331,152 @@ mod depth;
 
 #[cfg(test)]
 mod test {
+    use std::borrow::Cow;
+
     use crate::depth::boop::SomeStruct;
-    use jni_cli_macro::java_class;
+    use jni_cli_core::DirectBuffer;
+    use jni_cli_macro::{java_class, java_enum};
 
     pub struct SomeStruct2;
 
+    pub struct MutexStruct(std::cell::Cell<i64>);
+
+    #[java_class("beep.boop", sync = "mutex")]
+    impl MutexStruct {
+        fn get(&self) -> i64 {
+            self.0.get()
+        }
+
+        fn set(&mut self, value: i64) {
+            self.0.set(value)
+        }
+    }
+
+    pub struct UnsynchronizedStruct;
+
+    #[java_class("beep.boop", sync = "none")]
+    impl UnsynchronizedStruct {
+        fn len(&self, string: String) -> i64 {
+            string.len() as i64
+        }
+    }
+
+    pub struct ThreadConfinedStruct(std::rc::Rc<i64>);
+
+    #[java_class("beep.boop", sync = "thread_confined")]
+    impl ThreadConfinedStruct {
+        fn new(value: i64) -> Self {
+            ThreadConfinedStruct(std::rc::Rc::new(value))
+        }
+
+        fn get(&self) -> i64 {
+            *self.0
+        }
+    }
+
+    pub struct PrimitiveStruct;
+
+    /// every java primitive and primitive array, in both directions
+    #[java_class("beep.boop")]
+    impl PrimitiveStruct {
+        fn scalars(_z: bool, _b: i8, _s: i16, c: u16, _ch: char) -> u16 {
+            c
+        }
+
+        fn wide_scalars(_i: i32, _j: i64, _f: f32, d: f64) -> f64 {
+            d
+        }
+
+        fn boxed(
+            z: Option<bool>,
+            _b: Option<i8>,
+            _s: Option<i16>,
+            _ch: Option<char>,
+        ) -> Option<bool> {
+            z
+        }
+
+        fn arrays(_z: Vec<bool>, _b: Vec<i8>, _ub: Vec<u8>, _s: Vec<i16>, c: Vec<u16>) -> Vec<u16> {
+            c
+        }
+
+        fn wide_arrays(_i: Vec<i32>, _j: Vec<i64>, _f: Vec<f32>, d: Option<Vec<f64>>) -> Vec<f64> {
+            d.unwrap_or_default()
+        }
+
+        fn char(c: char) -> char {
+            c
+        }
+
+        fn sum(values: &[i32], _bytes: &[u8]) -> i64 {
+            values.iter().map(|&value| i64::from(value)).sum()
+        }
+
+        fn scale(&self, values: &mut [f64], by: f64) {
+            values.iter_mut().for_each(|value| *value *= by)
+        }
+
+        fn count(text: &str, separator: Cow<str>) -> i32 {
+            text.split(separator.as_ref()).count() as i32
+        }
+    }
+
+    pub struct BufferStruct(Box<[u8]>);
+
+    #[java_class("beep.boop", sync = "mutex")]
+    impl BufferStruct {
+        fn new(len: i32) -> Self {
+            BufferStruct(vec![0; len as usize].into_boxed_slice())
+        }
+
+        fn view(&mut self) -> DirectBuffer<'_> {
+            // the boxed slice is never reallocated
+            unsafe { DirectBuffer::new_mut(&mut self.0) }
+        }
+
+        fn copy_from(&mut self, buffer: Option<DirectBuffer>) {
+            if let Some(buffer) = buffer {
+                let len = buffer.len().min(self.0.len());
+                self.0[..len].copy_from_slice(&buffer[..len]);
+            }
+        }
+    }
+
+    #[java_enum("beep.boop")]
+    #[derive(Clone, Copy)]
+    pub enum PaddingDirection {
+        Left,
+        Right,
+    }
+
+    #[java_enum("beep.boop")]
+    pub enum TruncationStrategy {
+        LongestFirst { max_length: i64 },
+        Fixed(i32, Option<PaddingDirection>),
+        Words(Vec<String>, Vec<u8>),
+        DoNotTruncate,
+    }
+
+    pub struct PaddingStruct(PaddingDirection);
+
+    #[java_class("beep.boop")]
+    impl PaddingStruct {
+        fn new(direction: PaddingDirection) -> Self {
+            PaddingStruct(direction)
+        }
+
+        fn direction(&self) -> PaddingDirection {
+            self.0
+        }
+
+        fn truncation(&self, strategy: Option<TruncationStrategy>) -> TruncationStrategy {
+            strategy.unwrap_or(TruncationStrategy::Fixed(0, Some(self.0)))
+        }
+    }
+
     #[java_class("beep.boop")]
     impl SomeStruct2 {
+        #[allow(non_snake_case)]
         fn newFrom(_s: String, _idx: i32) -> SomeStruct2 {
             SomeStruct2
         }
@@ -184,5 +488,23 @@ mod test {
         fn do_more_even_more_stuff(&self, _string: String) -> SomeStruct {
             SomeStruct
         }
+
+        fn try_parse(&self, string: String) -> Result<i64, std::num::ParseIntError> {
+            string.parse()
+        }
+
+        fn find(&self, string: Option<String>) -> Option<i64> {
+            string.map(|string| string.len() as i64)
+        }
+
+        fn merge(&mut self, _other: &Self, _some_struct: &mut SomeStruct) -> i64 {
+            0
+        }
+
+        fn absorb(&self, _some_struct: SomeStruct) {}
+
+        fn into_len(self, string: String) -> i64 {
+            string.len() as i64
+        }
     }
 }
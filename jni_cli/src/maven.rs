@@ -0,0 +1,119 @@
+use std::{fs, path::Path, process::Stdio};
+
+use color_eyre::eyre;
+use tokio::process::Command;
+
+use crate::config::{Config, Lang};
+
+const POM_TEMPLATE: &str = include_str!("template/pom.xml");
+
+/// Id of the server in settings.xml holding the credentials of the repository `publish`
+/// uploads to
+pub const REPOSITORY_ID: &str = "jni-repository";
+
+/// Scaffolds the maven project in the output directory. An existing `pom.xml` is left alone, so
+/// edits to it survive running `init` again
+pub fn init(config: &Config) -> eyre::Result<()> {
+    let out = &config.output_dir;
+    let path = out.join("pom.xml");
+    if path.exists() {
+        println!("Keeping {}", path.display());
+    } else {
+        fs::create_dir_all(out)?;
+        fs::write(&path, render_pom(config)?)?;
+        println!("Created {}", path.display());
+    }
+    fs::create_dir_all(out.join(config.lang.source_dir()))?;
+    fs::create_dir_all(out.join("src/main/resources"))?;
+    Ok(())
+}
+
+/// The `group_id`, `artifact_id` and `version` of the `group:artifact:version` dependencies
+pub fn dependency_coordinates(dependencies: &[String]) -> eyre::Result<Vec<serde_json::Value>> {
+    dependencies
+        .iter()
+        .map(
+            |dependency| match dependency.split(':').collect::<Vec<_>>()[..] {
+                [group_id, artifact_id, version] => Ok(serde_json::json!({
+                    "group_id": group_id,
+                    "artifact_id": artifact_id,
+                    "version": version,
+                })),
+                _ => Err(eyre::eyre!(
+                    "Expected a dependency as group:artifact:version, got {dependency}"
+                )),
+            },
+        )
+        .collect()
+}
+
+fn render_pom(config: &Config) -> eyre::Result<String> {
+    let dependencies = dependency_coordinates(&config.dependencies)?;
+    let reg = handlebars::Handlebars::new();
+    Ok(reg.render_template(
+        POM_TEMPLATE,
+        &serde_json::json!({
+            "package_name": config.artifact_id,
+            "group_id": config.group,
+            "version": config.version,
+            "jvm_target": config.jvm_target,
+            "kotlin": config.lang == Lang::Kotlin,
+            "dependencies": dependencies,
+            "repository_id": REPOSITORY_ID,
+        }),
+    )?)
+}
+
+/// Runs maven in the project in `out` with `args`
+pub async fn run(out: &Path, args: &[String]) -> eyre::Result<()> {
+    if !out.join("pom.xml").exists() {
+        return Err(eyre::eyre!(
+            "{} doesn't exist, run `cli init` first",
+            out.join("pom.xml").display()
+        ));
+    }
+    let mvn = if cfg!(windows) { "mvn.cmd" } else { "mvn" };
+    let status = Command::new(mvn)
+        .args(args)
+        .current_dir(out)
+        .stdout(Stdio::inherit())
+        .stderr(Stdio::inherit())
+        .status()
+        .await?;
+    if !status.success() {
+        return Err(eyre::eyre!("mvn {} failed with {status}", args.join(" ")));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use crate::config::{Config, JniMetadata, Lang};
+
+    use super::render_pom;
+
+    #[test]
+    fn test_render_pom() {
+        let metadata = JniMetadata {
+            group: Some("dev.gigapixel".into()),
+            dependencies: vec!["com.google.guava:guava:32.1.1-jre".into()],
+            ..JniMetadata::default()
+        };
+        let mut config =
+            Config::resolve(metadata, JniMetadata::default(), "tokenizers", "0.2.0").expect("ok");
+        let pom = render_pom(&config).expect("valid pom");
+        assert!(pom.contains("<artifactId>tokenizers</artifactId>\n    <version>0.2.0</version>"));
+        assert!(pom.contains(
+            "<groupId>com.google.guava</groupId>\n            <artifactId>guava</artifactId>\n            <version>32.1.1-jre</version>"
+        ));
+        assert!(pom.contains("<artifactId>kotlin-maven-plugin</artifactId>"));
+
+        config.lang = Lang::Java;
+        let pom = render_pom(&config).expect("valid pom");
+        assert!(!pom.contains("kotlin"));
+        assert!(pom.contains("<artifactId>maven-compiler-plugin</artifactId>"));
+
+        config.dependencies = vec!["guava".into()];
+        assert!(render_pom(&config).is_err());
+    }
+}
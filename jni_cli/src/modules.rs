@@ -0,0 +1,642 @@
+use std::{
+    collections::BTreeSet,
+    fs,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+use cargo_metadata::Package;
+use color_eyre::eyre::{self, Context};
+use jni_cli_core::token_processing::RustModule;
+use syn::{punctuated::Punctuated, Attribute, Expr, ImplItem, Item, Lit, LitStr, Meta, Token};
+
+/// Target kinds whose root file is the crate's library, one of them builds the cdylib
+const LIB_KINDS: [&str; 5] = ["lib", "rlib", "dylib", "cdylib", "staticlib"];
+
+/// The features of `package` enabled by the cli flags, following the `[features]` table
+pub fn enabled_features(
+    package: &Package,
+    features: &[String],
+    all_features: bool,
+    no_default_features: bool,
+) -> BTreeSet<String> {
+    let mut pending: Vec<&str> = if all_features {
+        package.features.keys().map(String::as_str).collect()
+    } else {
+        features.iter().map(String::as_str).collect()
+    };
+    if !no_default_features && package.features.contains_key("default") {
+        pending.push("default");
+    }
+    let mut enabled = BTreeSet::new();
+    while let Some(feature) = pending.pop() {
+        // `dep:serde` and `serde/std` enable dependencies, not features of this crate
+        if feature.starts_with("dep:") || feature.contains('/') {
+            continue;
+        }
+        if enabled.insert(feature.to_string()) {
+            if let Some(implied) = package.features.get(feature) {
+                pending.extend(implied.iter().map(String::as_str));
+            }
+        }
+    }
+    enabled
+}
+
+/// The options printed by `rustc --print cfg`, e.g. `unix` or `target_os="linux"`
+type CfgOptions = BTreeSet<(String, Option<String>)>;
+
+/// For how many of the targets an item is compiled
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Enabled {
+    All,
+    Some,
+    None,
+}
+
+/// Evaluates `#[cfg]` predicates like rustc does for the release build of a set of targets
+/// with a set of features
+pub struct CfgSet {
+    features: BTreeSet<String>,
+    /// the options of every target the library is built for
+    targets: Vec<CfgOptions>,
+}
+
+impl CfgSet {
+    /// The cfg of the release build for each target triple, or for the host if there are
+    /// none, read from `rustc --print cfg`
+    pub fn for_targets(features: BTreeSet<String>, targets: &[String]) -> eyre::Result<CfgSet> {
+        let targets = if targets.is_empty() {
+            vec![target_options(None)?]
+        } else {
+            targets
+                .iter()
+                .map(|target| target_options(Some(target)))
+                .collect::<eyre::Result<_>>()?
+        };
+        Ok(CfgSet { features, targets })
+    }
+
+    /// For how many of the targets the `#[cfg]` and `#[cfg_attr(.., cfg(..))]` attributes in
+    /// `attrs` keep the item
+    fn enabled(&self, attrs: &[Attribute]) -> eyre::Result<Enabled> {
+        let mut count = 0;
+        for options in &self.targets {
+            if self.metas_enabled(options, attrs.iter().map(|attr| &attr.meta))? {
+                count += 1;
+            }
+        }
+        Ok(match count {
+            0 => Enabled::None,
+            count if count == self.targets.len() => Enabled::All,
+            _ => Enabled::Some,
+        })
+    }
+
+    /// Whether none of the attributes `metas` excludes the item for the target with `options`
+    fn metas_enabled<'m>(
+        &self,
+        options: &CfgOptions,
+        metas: impl IntoIterator<Item = &'m Meta>,
+    ) -> eyre::Result<bool> {
+        for meta in metas {
+            if meta.path().is_ident("cfg") {
+                if !self.eval(options, &meta.require_list()?.parse_args()?)? {
+                    return Ok(false);
+                }
+            } else if meta.path().is_ident("cfg_attr") {
+                let args = meta
+                    .require_list()?
+                    .parse_args_with(Punctuated::<Meta, Token![,]>::parse_terminated)?;
+                let args: Vec<_> = args.into_iter().collect();
+                let Some((predicate, attrs)) = args.split_first() else {
+                    return Err(eyre::eyre!("Expected a predicate in #[cfg_attr(..)]"));
+                };
+                // the cli only sees the attributes written on the item
+                if let Some(attr) = attrs.iter().find(|attr| is_java_attr(attr)) {
+                    return Err(eyre::eyre!(
+                        "#[cfg_attr(.., {})] isn't supported, put the attribute on the item \
+                         itself and make the item conditional with #[cfg] instead",
+                        path_name(attr.path())?
+                    ));
+                }
+                if self.eval(options, predicate)? && !self.metas_enabled(options, attrs)? {
+                    return Ok(false);
+                }
+            }
+        }
+        Ok(true)
+    }
+
+    fn eval(&self, options: &CfgOptions, predicate: &Meta) -> eyre::Result<bool> {
+        match predicate {
+            Meta::Path(path) => {
+                let name = path_name(path)?;
+                Ok(options.contains(&(name, None)))
+            }
+            Meta::NameValue(name_value) => {
+                let name = path_name(&name_value.path)?;
+                let value = match &name_value.value {
+                    Expr::Lit(lit) => match &lit.lit {
+                        Lit::Str(value) => value.value(),
+                        _ => return Err(eyre::eyre!("Expected a string in cfg({name} = ..)")),
+                    },
+                    _ => return Err(eyre::eyre!("Expected a string in cfg({name} = ..)")),
+                };
+                if name == "feature" {
+                    Ok(self.features.contains(&value))
+                } else {
+                    Ok(options.contains(&(name, Some(value))))
+                }
+            }
+            Meta::List(list) => {
+                let nested = list
+                    .parse_args_with(Punctuated::<Meta, Token![,]>::parse_terminated)?
+                    .into_iter()
+                    .map(|predicate| self.eval(options, &predicate))
+                    .collect::<eyre::Result<Vec<_>>>()?;
+                match (path_name(&list.path)?.as_str(), nested.as_slice()) {
+                    ("all", nested) => Ok(nested.iter().all(|enabled| *enabled)),
+                    ("any", nested) => Ok(nested.iter().any(|enabled| *enabled)),
+                    ("not", [enabled]) => Ok(!enabled),
+                    (name, _) => Err(eyre::eyre!("Unsupported cfg predicate {name}(..)")),
+                }
+            }
+        }
+    }
+}
+
+/// The cfg options of `target`, or the host, as `cargo build --release` compiles with them,
+/// which leaves out `debug_assertions`
+fn target_options(target: Option<&str>) -> eyre::Result<CfgOptions> {
+    let rustc = std::env::var("RUSTC").unwrap_or_else(|_| "rustc".to_string());
+    let mut command = Command::new(&rustc);
+    command.args(["--print", "cfg", "-O", "-C", "debug-assertions=off"]);
+    if let Some(target) = target {
+        command.args(["--target", target]);
+    }
+    let output = command
+        .output()
+        .wrap_err_with(|| format!("Failed to run {rustc} --print cfg"))?;
+    if !output.status.success() {
+        return Err(eyre::eyre!(
+            "{rustc} --print cfg failed with {}: {}",
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+    let options = String::from_utf8(output.stdout)?
+        .lines()
+        .map(|line| match line.split_once('=') {
+            Some((name, value)) => (name.to_string(), Some(value.trim_matches('"').into())),
+            None => (line.to_string(), None),
+        })
+        .collect();
+    Ok(options)
+}
+
+fn path_name(path: &syn::Path) -> eyre::Result<String> {
+    path.get_ident().map(ToString::to_string).ok_or_else(|| {
+        let segments: Vec<_> = path.segments.iter().map(|s| s.ident.to_string()).collect();
+        eyre::eyre!("Unsupported cfg name {}", segments.join("::"))
+    })
+}
+
+/// The modules of the library target of `package`, following the `mod` declarations from the
+/// crate root and leaving out the ones disabled by `cfg`
+pub fn load_crate(package: &Package, cfg: &CfgSet) -> eyre::Result<Vec<RustModule>> {
+    let lib = package
+        .targets
+        .iter()
+        .find(|target| {
+            target
+                .kind
+                .iter()
+                .any(|kind| LIB_KINDS.contains(&kind.as_str()))
+        })
+        .ok_or_else(|| eyre::eyre!("{} has no library target", package.name))?;
+    let mut loader = ModuleLoader {
+        cfg,
+        modules: vec![],
+        partial: None,
+    };
+    loader.load_file("crate".to_string(), lib.src_path.as_std_path(), true)?;
+    Ok(loader.modules)
+}
+
+/// Where the files of the modules declared in a module are looked up
+struct ModuleDirs {
+    /// directory of the file the items are in, `#[path]` and `include!` are relative to it
+    file_dir: PathBuf,
+    /// directory of the module's submodules, `a/b` for the items of `a/b.rs` or `a/b/mod.rs`
+    mod_dir: PathBuf,
+    /// whether the items are in an inline `mod a { .. }`, making `#[path]` relative to `mod_dir`
+    inline: bool,
+}
+
+struct ModuleLoader<'a> {
+    cfg: &'a CfgSet,
+    modules: Vec<RustModule>,
+    /// the `#[cfg]` of an enclosing module compiled for only some of the targets, which can't
+    /// hold `#[java_class]`es or `#[java_enum]`s as the generated classes are shared by all
+    partial: Option<String>,
+}
+
+impl ModuleLoader<'_> {
+    /// Loads the module `path` from `file`. `owns_dir` is set for the crate root, `mod.rs` and
+    /// `#[path]` files, whose submodules are next to them instead of in a directory of their name
+    fn load_file(&mut self, path: String, file: &Path, owns_dir: bool) -> eyre::Result<()> {
+        let syn::File { attrs, items, .. } = parse_file(file)?;
+        let Some(outer) = self.enter(&attrs)? else {
+            return Ok(());
+        };
+        let file_dir = file.parent().unwrap_or(Path::new("")).to_path_buf();
+        let mod_dir = match file.file_stem() {
+            Some(stem) if !owns_dir && stem != "mod" => file_dir.join(stem),
+            _ => file_dir.clone(),
+        };
+        let dirs = ModuleDirs {
+            file_dir,
+            mod_dir,
+            inline: false,
+        };
+        self.load_module(path, items, &dirs)?;
+        self.partial = outer;
+        Ok(())
+    }
+
+    /// Starts loading the items under `attrs`, `None` if no target compiles them. Otherwise
+    /// returns the enclosing [`ModuleLoader::partial`] to restore once they're loaded
+    fn enter(&mut self, attrs: &[Attribute]) -> eyre::Result<Option<Option<String>>> {
+        let outer = self.partial.clone();
+        match self.cfg.enabled(attrs)? {
+            Enabled::None => return Ok(None),
+            Enabled::Some if self.partial.is_none() => self.partial = Some(cfg_attrs(attrs)),
+            _ => {}
+        }
+        Ok(Some(outer))
+    }
+
+    fn load_module(
+        &mut self,
+        path: String,
+        items: Vec<Item>,
+        dirs: &ModuleDirs,
+    ) -> eyre::Result<()> {
+        let items = self.module_items(&path, items, dirs)?;
+        self.modules.push(RustModule { path, items });
+        Ok(())
+    }
+
+    /// The items of the module `path` enabled by `cfg` and spliced in by `include!`, loading the
+    /// submodules declared among them
+    fn module_items(
+        &mut self,
+        path: &str,
+        items: Vec<Item>,
+        dirs: &ModuleDirs,
+    ) -> eyre::Result<Vec<Item>> {
+        let mut module_items = vec![];
+        for item in items {
+            match item {
+                Item::Mod(item_mod) => {
+                    let Some(outer) = self.enter(&item_mod.attrs)? else {
+                        continue;
+                    };
+                    let child = format!("{path}::{}", item_mod.ident);
+                    let path_attr = path_attr(&item_mod.attrs)?;
+                    match item_mod.content {
+                        Some((_, items)) => {
+                            let mod_dir = dirs
+                                .mod_dir
+                                .join(path_attr.unwrap_or_else(|| item_mod.ident.to_string()));
+                            let dirs = ModuleDirs {
+                                file_dir: dirs.file_dir.clone(),
+                                mod_dir,
+                                inline: true,
+                            };
+                            self.load_module(child, items, &dirs)?;
+                        }
+                        None => match path_attr {
+                            Some(file) if dirs.inline => {
+                                self.load_file(child, &dirs.mod_dir.join(file), true)?
+                            }
+                            Some(file) => self.load_file(child, &dirs.file_dir.join(file), true)?,
+                            None => {
+                                let name = item_mod.ident.to_string();
+                                let file = dirs.mod_dir.join(format!("{name}.rs"));
+                                if file.exists() {
+                                    self.load_file(child, &file, false)?;
+                                } else {
+                                    let file = dirs.mod_dir.join(name).join("mod.rs");
+                                    self.load_file(child, &file, true)?;
+                                }
+                            }
+                        },
+                    }
+                    self.partial = outer;
+                }
+                Item::Macro(item_macro) if item_macro.mac.path.is_ident("include") => {
+                    let Some(outer) = self.enter(&item_macro.attrs)? else {
+                        continue;
+                    };
+                    let Ok(file) = item_macro.mac.parse_body::<LitStr>() else {
+                        println!(
+                            "Skipping include!({}) in {path}, only string literal paths are followed",
+                            item_macro.mac.tokens
+                        );
+                        self.partial = outer;
+                        continue;
+                    };
+                    let file = dirs.file_dir.join(file.value());
+                    let included = parse_file(&file)?;
+                    let dirs = ModuleDirs {
+                        file_dir: file.parent().unwrap_or(Path::new("")).to_path_buf(),
+                        mod_dir: dirs.mod_dir.clone(),
+                        inline: dirs.inline,
+                    };
+                    module_items.extend(self.module_items(path, included.items, &dirs)?);
+                    self.partial = outer;
+                }
+                item => {
+                    let Some(outer) = self.enter(item_attrs(&item))? else {
+                        continue;
+                    };
+                    module_items.push(self.java_item(path, item)?);
+                    self.partial = outer;
+                }
+            }
+        }
+        Ok(module_items)
+    }
+
+    /// Leaves out the methods of a `#[java_class]` impl block and the variants of a
+    /// `#[java_enum]` that no target compiles, so that they get no bindings either
+    fn java_item(&self, path: &str, mut item: Item) -> eyre::Result<Item> {
+        if !item_attrs(&item)
+            .iter()
+            .any(|attr| is_java_attr(&attr.meta))
+        {
+            return Ok(item);
+        }
+        let partial = |cfg: &str| {
+            eyre::eyre!(
+                "A #[java_class] or #[java_enum] in {path} is only compiled for some of the \
+                 targets due to {cfg}, but the generated classes are shared by all of them"
+            )
+        };
+        if let Some(cfg) = &self.partial {
+            return Err(partial(cfg));
+        }
+        let enabled = |attrs: &[Attribute]| match self.cfg.enabled(attrs)? {
+            Enabled::All => Ok(true),
+            Enabled::None => Ok(false),
+            Enabled::Some => Err(partial(&cfg_attrs(attrs))),
+        };
+        match &mut item {
+            Item::Impl(item_impl) => {
+                let mut items = vec![];
+                for impl_item in item_impl.items.drain(..) {
+                    if enabled(impl_item_attrs(&impl_item))? {
+                        items.push(impl_item);
+                    }
+                }
+                item_impl.items = items;
+            }
+            Item::Enum(item_enum) => {
+                let mut variants = Punctuated::new();
+                for variant in std::mem::take(&mut item_enum.variants) {
+                    if enabled(&variant.attrs)? {
+                        variants.push(variant);
+                    }
+                }
+                item_enum.variants = variants;
+            }
+            _ => {}
+        }
+        Ok(item)
+    }
+}
+
+/// Whether the attribute is `#[java_class]` or `#[java_enum]`, which the cli only recognizes
+/// by their bare name
+fn is_java_attr(meta: &Meta) -> bool {
+    meta.path().is_ident("java_class") || meta.path().is_ident("java_enum")
+}
+
+/// The `#[cfg]` and `#[cfg_attr]` attributes in `attrs`, for error messages
+fn cfg_attrs(attrs: &[Attribute]) -> String {
+    attrs
+        .iter()
+        .filter_map(|attr| {
+            let list = attr.meta.require_list().ok()?;
+            let name = list.path.get_ident()?;
+            (name == "cfg" || name == "cfg_attr").then(|| format!("#[{name}({})]", list.tokens))
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+fn parse_file(file: &Path) -> eyre::Result<syn::File> {
+    let rust =
+        fs::read_to_string(file).wrap_err_with(|| format!("Failed to read {}", file.display()))?;
+    syn::parse_file(&rust).wrap_err_with(|| format!("Failed to parse {}", file.display()))
+}
+
+/// The value of a `#[path = ".."]` attribute
+fn path_attr(attrs: &[Attribute]) -> eyre::Result<Option<String>> {
+    let Some(attr) = attrs.iter().find(|attr| attr.path().is_ident("path")) else {
+        return Ok(None);
+    };
+    match &attr.meta.require_name_value()?.value {
+        Expr::Lit(syn::ExprLit {
+            lit: Lit::Str(path),
+            ..
+        }) => Ok(Some(path.value())),
+        _ => Err(eyre::eyre!("Expected a string in #[path = ..]")),
+    }
+}
+
+fn impl_item_attrs(item: &ImplItem) -> &[Attribute] {
+    match item {
+        ImplItem::Const(item) => &item.attrs,
+        ImplItem::Fn(item) => &item.attrs,
+        ImplItem::Type(item) => &item.attrs,
+        ImplItem::Macro(item) => &item.attrs,
+        _ => &[],
+    }
+}
+
+fn item_attrs(item: &Item) -> &[Attribute] {
+    match item {
+        Item::Const(item) => &item.attrs,
+        Item::Enum(item) => &item.attrs,
+        Item::ExternCrate(item) => &item.attrs,
+        Item::Fn(item) => &item.attrs,
+        Item::ForeignMod(item) => &item.attrs,
+        Item::Impl(item) => &item.attrs,
+        Item::Macro(item) => &item.attrs,
+        Item::Mod(item) => &item.attrs,
+        Item::Static(item) => &item.attrs,
+        Item::Struct(item) => &item.attrs,
+        Item::Trait(item) => &item.attrs,
+        Item::TraitAlias(item) => &item.attrs,
+        Item::Type(item) => &item.attrs,
+        Item::Union(item) => &item.attrs,
+        Item::Use(item) => &item.attrs,
+        _ => &[],
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{collections::BTreeSet, fs, path::Path};
+
+    use super::{CfgSet, Enabled, ModuleLoader};
+
+    fn write(root: &Path, file: &str, rust: &str) {
+        let path = root.join(file);
+        fs::create_dir_all(path.parent().expect("has a parent")).expect("created dir");
+        fs::write(path, rust).expect("written");
+    }
+
+    #[test]
+    fn test_load_modules() {
+        let dir = tempfile::tempdir().expect("temp dir");
+        let root = dir.path();
+        write(
+            root,
+            "src/lib.rs",
+            r#"
+            mod a;
+            mod b;
+            #[cfg(test)]
+            mod tests;
+            #[cfg(feature = "extra")]
+            mod extra;
+            #[cfg(not(feature = "extra"))]
+            mod basic { mod nested; }
+            #[path = "other/renamed.rs"]
+            mod c;
+            include!("included.rs");
+            #[cfg(all(unix, not(unix)))]
+            struct Never;
+            "#,
+        );
+        write(root, "src/a.rs", "mod child; struct A;");
+        write(root, "src/a/child.rs", "struct Child;");
+        write(root, "src/b/mod.rs", "mod child;");
+        write(root, "src/b/child.rs", "");
+        write(root, "src/tests.rs", "this doesn't parse");
+        write(root, "src/basic/nested.rs", "struct Nested;");
+        write(root, "src/other/renamed.rs", "mod sibling;");
+        write(root, "src/other/sibling.rs", "");
+        write(root, "src/included.rs", "struct Included;");
+
+        let cfg = CfgSet {
+            features: BTreeSet::new(),
+            targets: vec![[("unix".to_string(), None)].into_iter().collect()],
+        };
+        let mut loader = ModuleLoader {
+            cfg: &cfg,
+            modules: vec![],
+            partial: None,
+        };
+        loader
+            .load_file("crate".into(), &root.join("src/lib.rs"), true)
+            .expect("loaded");
+        let paths: Vec<_> = loader.modules.iter().map(|m| m.path.as_str()).collect();
+        assert_eq!(
+            paths,
+            [
+                "crate::a::child",
+                "crate::a",
+                "crate::b::child",
+                "crate::b",
+                "crate::basic::nested",
+                "crate::basic",
+                "crate::c::sibling",
+                "crate::c",
+                "crate",
+            ]
+        );
+        let root_module = loader.modules.last().expect("has the root");
+        let item_count = root_module.items.len();
+        assert_eq!(item_count, 1, "only the included struct is left");
+    }
+
+    #[test]
+    fn test_cfg_differing_between_targets() {
+        let target = |os: &str| {
+            [
+                ("unix".to_string(), None),
+                ("target_os".to_string(), Some(os.to_string())),
+            ]
+            .into_iter()
+            .collect()
+        };
+        let cfg = CfgSet {
+            features: BTreeSet::new(),
+            targets: vec![target("linux"), target("macos")],
+        };
+        let attrs = |rust: &str| {
+            syn::parse_str::<syn::ItemStruct>(rust)
+                .expect("parsed")
+                .attrs
+        };
+        let enabled = |rust: &str| cfg.enabled(&attrs(rust)).expect("evaluated");
+        assert_eq!(enabled("#[cfg(unix)] struct A;"), Enabled::All);
+        assert_eq!(enabled("#[cfg(debug_assertions)] struct A;"), Enabled::None);
+        assert_eq!(
+            enabled(r#"#[cfg(target_os = "linux")] struct A;"#),
+            Enabled::Some
+        );
+        assert_eq!(
+            enabled(r#"#[cfg_attr(target_os = "linux", cfg(unix), cfg(windows))] struct A;"#),
+            Enabled::Some
+        );
+        assert!(cfg
+            .enabled(&attrs(r#"#[cfg_attr(unix, java_class("beep"))] struct A;"#))
+            .is_err_and(|err| err.to_string().contains("#[cfg_attr(.., java_class)]")));
+
+        let load = |rust: &str| {
+            let dir = tempfile::tempdir().expect("temp dir");
+            write(dir.path(), "src/lib.rs", rust);
+            let mut loader = ModuleLoader {
+                cfg: &cfg,
+                modules: vec![],
+                partial: None,
+            };
+            loader
+                .load_file("crate".into(), &dir.path().join("src/lib.rs"), true)
+                .map(|()| loader.modules)
+        };
+        let modules = load(
+            r#"
+            #[cfg(target_os = "linux")]
+            mod linux { struct OnlyHere; }
+            #[java_class("beep")]
+            impl A {
+                fn everywhere() {}
+                #[cfg(windows)]
+                fn nowhere() {}
+                #[cfg_attr(unix, cfg(debug_assertions))]
+                fn also_nowhere() {}
+            }
+            "#,
+        )
+        .expect("loaded");
+        let root_module = modules.last().expect("has the root");
+        let syn::Item::Impl(item_impl) = &root_module.items[0] else {
+            panic!("expected the impl block");
+        };
+        assert_eq!(item_impl.items.len(), 1, "only the enabled method is left");
+        for rust in [
+            r#"#[java_class("beep")] impl A { #[cfg(target_os = "linux")] fn f() {} }"#,
+            r#"#[cfg(target_os = "linux")] mod linux { #[java_enum("beep")] enum E {} }"#,
+        ] {
+            assert!(load(rust)
+                .is_err_and(|err| err.to_string().contains(r#"#[cfg(target_os = "linux")]"#)));
+        }
+    }
+}
@@ -0,0 +1,129 @@
+use std::{
+    env::consts,
+    io::{BufReader, Read},
+    process::Stdio,
+};
+
+use cargo_metadata::{camino::Utf8PathBuf, Message, PackageId};
+use color_eyre::eyre::{self, Context};
+use tokio::process::Command;
+
+/// Extensions of the dynamic libraries a `cdylib` target builds to on the supported platforms
+const CDYLIB_EXTENSIONS: [&str; 3] = ["so", "dylib", "dll"];
+
+/// The `<os>-<arch>` name of the platform the cli runs on, matching the names the generated
+/// `Library` object derives from the JVM's `os.name` and `os.arch`
+pub fn host_platform() -> String {
+    format!("{}-{}", consts::OS, consts::ARCH)
+}
+
+/// The `<os>-<arch>` name of the platform a rust target triple such as
+/// `aarch64-unknown-linux-gnu` builds for
+pub fn platform_for_triple(triple: &str) -> eyre::Result<String> {
+    let (arch, rest) = triple
+        .split_once('-')
+        .ok_or_else(|| eyre::eyre!("Invalid target triple {triple}"))?;
+    let arch = match arch {
+        "x86_64" => "x86_64",
+        "aarch64" | "arm64" => "aarch64",
+        "i586" | "i686" => "x86",
+        arch => arch,
+    };
+    let os = if rest.contains("linux") && !rest.contains("android") {
+        "linux"
+    } else if rest.contains("darwin") {
+        "macos"
+    } else if rest.contains("windows") {
+        "windows"
+    } else {
+        return Err(eyre::eyre!(
+            "Target {triple} isn't supported, only linux, macos and windows targets can be loaded by the JVM"
+        ));
+    };
+    Ok(format!("{os}-{arch}"))
+}
+
+/// Runs `cargo` (or a wrapper such as `cargo zigbuild`) to build the release cdylib of
+/// `package_id` with the extra `args`, for the host or the given target triple
+pub async fn build_cdylib(
+    cargo: &str,
+    args: &[String],
+    target: Option<&str>,
+    linker: Option<&str>,
+    package_id: &PackageId,
+) -> eyre::Result<Utf8PathBuf> {
+    let mut cargo = cargo.split_whitespace();
+    let program = cargo
+        .next()
+        .ok_or_else(|| eyre::eyre!("The cargo command is empty"))?;
+    let mut command = Command::new(program);
+    command.args(cargo).args([
+        "build",
+        "--release",
+        "--message-format=json-render-diagnostics",
+    ]);
+    command.args(args);
+    if let Some(target) = target {
+        command.args(["--target", target]);
+        if let Some(linker) = linker {
+            let triple = target.to_uppercase().replace(['-', '.'], "_");
+            command.env(format!("CARGO_TARGET_{triple}_LINKER"), linker);
+        }
+    }
+    let output = command
+        .stdout(Stdio::piped())
+        .stderr(Stdio::inherit())
+        .spawn()?
+        .wait_with_output()
+        .await?;
+    if !output.status.success() {
+        return Err(eyre::eyre!(
+            "cargo build for {} failed with {}",
+            target.unwrap_or("the host"),
+            output.status
+        ));
+    }
+    find_cdylib(output.stdout.as_slice(), package_id)
+}
+
+/// Finds the dynamic library built for the `cdylib` target of `package_id` in the output of
+/// `cargo build --message-format=json`
+pub fn find_cdylib<R: Read>(messages: R, package_id: &PackageId) -> eyre::Result<Utf8PathBuf> {
+    for message in Message::parse_stream(BufReader::new(messages)) {
+        let message = message.wrap_err("Failed to parse cargo build output")?;
+        let Message::CompilerArtifact(artifact) = message else {
+            continue;
+        };
+        if &artifact.package_id != package_id
+            || !artifact.target.kind.iter().any(|kind| kind == "cdylib")
+        {
+            continue;
+        }
+        if let Some(cdylib) = artifact.filenames.into_iter().find(|file| {
+            file.extension()
+                .is_some_and(|extension| CDYLIB_EXTENSIONS.contains(&extension))
+        }) {
+            return Ok(cdylib);
+        }
+    }
+    Err(eyre::eyre!(
+        "cargo didn't build a cdylib for {package_id}, is `crate-type = [\"cdylib\"]` set in Cargo.toml?"
+    ))
+}
+
+#[cfg(test)]
+mod test {
+    use super::platform_for_triple;
+
+    #[test]
+    fn test_platform_for_triple() {
+        let platform = |triple| platform_for_triple(triple).expect("supported");
+        assert_eq!(platform("x86_64-unknown-linux-gnu"), "linux-x86_64");
+        assert_eq!(platform("aarch64-unknown-linux-musl"), "linux-aarch64");
+        assert_eq!(platform("aarch64-apple-darwin"), "macos-aarch64");
+        assert_eq!(platform("x86_64-pc-windows-msvc"), "windows-x86_64");
+        assert_eq!(platform("i686-pc-windows-gnu"), "windows-x86");
+        assert!(platform_for_triple("aarch64-linux-android").is_err());
+        assert!(platform_for_triple("wasm32").is_err());
+    }
+}
@@ -4,8 +4,10 @@
  */
 
 plugins {
+{{#if kotlin}}
     // Apply the org.jetbrains.kotlin.jvm Plugin to add support for Kotlin.
     id("org.jetbrains.kotlin.jvm") version "1.9.0"
+{{/if}}
     id("maven-publish")
 
     // Apply the java-library plugin for API and implementation separation.
@@ -15,18 +17,17 @@ plugins {
 repositories {
     // Use Maven Central for resolving dependencies.
     mavenCentral()
-    maven {
-        url = uri("https://bio.informatik.uni-jena.de/repository/libs-release-oss/")
-    }
 }
 
 dependencies {
     // This dependency is exported to consumers, that is to say found on their compile classpath.
-    implementation("cz.adamh:native-utils:1.0")
     api("org.apache.commons:commons-math3:3.6.1")
     // This dependency is used internally, and not exposed to consumers on their own compile
     // classpath.
     implementation("com.google.guava:guava:32.1.1-jre")
+{{#each dependencies}}
+    implementation("{{{this}}}")
+{{/each}}
 }
 
 testing {
@@ -34,23 +35,43 @@ testing {
         // Configure the built-in test suite
         val test by
                 getting(JvmTestSuite::class) {
+{{#if kotlin}}
                     // Use Kotlin Test test framework
                     useKotlinTest("1.9.0")
+{{else}}
+                    // Use JUnit Jupiter test framework
+                    useJUnitJupiter()
+{{/if}}
                 }
     }
 }
 
 // Apply a specific Java toolchain to ease working on different environments.
-java { toolchain { languageVersion.set(JavaLanguageVersion.of(19)) } }
+java { toolchain { languageVersion.set(JavaLanguageVersion.of({{jvm_target}})) } }
 
 publishing {
     publications {
         create<MavenPublication>("maven") {
             groupId = "{{group_id}}"
             artifactId = "{{package_name}}"
-            version = "1.1"
+            version = "{{version}}"
 
             from(components["java"])
         }
     }
+    repositories {
+        // set by `cli publish` when a repository is configured, credentials are read from the
+        // jniRepositoryUsername and jniRepositoryPassword properties
+        val repositoryUrl = findProperty("{{repository_url_property}}") as String?
+        if (repositoryUrl != null) {
+            maven {
+                name = "configured"
+                url = uri(repositoryUrl)
+                credentials {
+                    username = findProperty("jniRepositoryUsername") as String?
+                    password = findProperty("jniRepositoryPassword") as String?
+                }
+            }
+        }
+    }
 }
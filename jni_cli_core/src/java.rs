@@ -0,0 +1,871 @@
+use itertools::Itertools;
+use proc_macro2::Span;
+use syn::Error;
+
+use crate::{
+    manifest::{Class, Enum, Method, RefType, TypeRef, Variant},
+    token_processing::{returns_buffers, KotlinClass, RUST_EXCEPTION, RUST_PANIC_EXCEPTION},
+    PRIMITIVES, PRIMITIVE_ARRAYS,
+};
+
+/// Java keywords and literals, which rust identifiers and package names may collide with
+pub const JAVA_KEYWORDS: &[&str] = &[
+    "abstract",
+    "assert",
+    "boolean",
+    "break",
+    "byte",
+    "case",
+    "catch",
+    "char",
+    "class",
+    "const",
+    "continue",
+    "default",
+    "do",
+    "double",
+    "else",
+    "enum",
+    "extends",
+    "final",
+    "finally",
+    "float",
+    "for",
+    "goto",
+    "if",
+    "implements",
+    "import",
+    "instanceof",
+    "int",
+    "interface",
+    "long",
+    "native",
+    "new",
+    "package",
+    "private",
+    "protected",
+    "public",
+    "return",
+    "short",
+    "static",
+    "strictfp",
+    "super",
+    "switch",
+    "synchronized",
+    "this",
+    "throw",
+    "throws",
+    "transient",
+    "try",
+    "void",
+    "volatile",
+    "while",
+    "true",
+    "false",
+    "null",
+];
+
+/// The java name of a method or parameter. `new` becomes the static factory `create` unless
+/// the class has one already, other keywords get a trailing underscore
+fn java_name(name: &str, class: &Class) -> String {
+    if name == "new" && !class.methods.iter().any(|method| method.name == "create") {
+        "create".to_string()
+    } else if JAVA_KEYWORDS.contains(&name) {
+        format!("{name}_")
+    } else {
+        name.to_string()
+    }
+}
+
+/// The java type of a resolved type, boxing nullable primitives
+fn java_type(ty: &TypeRef) -> Result<String, Error> {
+    let kotlin = ty.kotlin.as_ref().ok_or_else(|| {
+        Error::new(
+            Span::call_site(),
+            format!("The type of {} isn't resolved. This is a bug.", ty.rust),
+        )
+    })?;
+    Ok(java_type_of_kotlin(kotlin, ty.nullable))
+}
+
+fn java_type_of_kotlin(kotlin: &str, nullable: bool) -> String {
+    if let Some(primitive) = PRIMITIVES.iter().find(|p| p.kotlin == kotlin) {
+        return if nullable {
+            primitive.boxed_name()
+        } else {
+            primitive.java
+        }
+        .to_string();
+    }
+    if let Some(array) = PRIMITIVE_ARRAYS.iter().find(|a| a.kotlin == kotlin) {
+        return array.java.to_string();
+    }
+    match kotlin
+        .strip_prefix("Array<")
+        .and_then(|inner| inner.strip_suffix('>'))
+    {
+        Some(inner) => format!("{}[]", java_type_of_kotlin(inner, true)),
+        None if kotlin == "Unit" => "void".to_string(),
+        None => kotlin.to_string(),
+    }
+}
+
+fn java_throws(method: &Method) -> String {
+    if method.throws {
+        format!(" throws {RUST_EXCEPTION}")
+    } else {
+        String::new()
+    }
+}
+
+/// Statements releasing the `#[java_class]` objects passed by value, which rust takes
+/// ownership of
+fn java_release_consumed(class: &Class, method: &Method) -> Vec<String> {
+    let receiver =
+        matches!(method.receiver, Some(RefType::Owned)).then(|| "releaseIfConsumed();".to_string());
+    let params = method
+        .params
+        .iter()
+        .filter(|param| param.ty.object && param.ty.borrow.is_none())
+        .map(|param| {
+            let name = java_name(&param.name, class);
+            if param.ty.nullable {
+                format!("if ({name} != null) {name}.releaseIfConsumed();")
+            } else {
+                format!("{name}.releaseIfConsumed();")
+            }
+        });
+    receiver.into_iter().chain(params).collect()
+}
+
+/// The public method calling the native one, registering the cleaner of returned objects and
+/// releasing the consumed ones
+fn java_method(class: &Class, method: &Method) -> Result<String, Error> {
+    let params = method
+        .params
+        .iter()
+        .map(|param| {
+            Ok(format!(
+                "{} {}",
+                java_type(&param.ty)?,
+                java_name(&param.name, class)
+            ))
+        })
+        .collect::<Result<Vec<_>, Error>>()?
+        .join(", ");
+    let args = method
+        .receiver
+        .map(|_| "this".to_string())
+        .into_iter()
+        .chain(
+            method
+                .params
+                .iter()
+                .map(|param| java_name(&param.name, class)),
+        )
+        .join(", ");
+    let call = format!("{}({args})", method.native_name);
+    let output = match &method.returns {
+        Some(ty) => Some((java_type(ty)?, ty)),
+        None => None,
+    };
+    let release_consumed = java_release_consumed(class, method);
+
+    let mut body = vec![];
+    match (&output, release_consumed.is_empty()) {
+        (None, true) => body.push(format!("{call};")),
+        (None, false) => {
+            body.push("try {".to_string());
+            body.push(format!("    {call};"));
+            body.push("} finally {".to_string());
+            body.extend(release_consumed.iter().map(|line| format!("    {line}")));
+            body.push("}".to_string());
+        }
+        (Some((_, ty)), true) if !ty.object => body.push(format!("return {call};")),
+        (Some((output, _)), true) => body.push(format!("{output} obj = {call};")),
+        (Some((output, _)), false) => {
+            body.push(format!("{output} obj;"));
+            body.push("try {".to_string());
+            body.push(format!("    obj = {call};"));
+            body.push("} finally {".to_string());
+            body.extend(release_consumed.iter().map(|line| format!("    {line}")));
+            body.push("}".to_string());
+        }
+    }
+    if let Some((_, ty)) = output
+        .as_ref()
+        .filter(|(_, ty)| ty.object || !release_consumed.is_empty())
+    {
+        if ty.object && ty.nullable {
+            body.push("if (obj != null) obj.registerCleaner();".to_string());
+        } else if ty.object {
+            body.push("obj.registerCleaner();".to_string());
+        }
+        body.push("return obj;".to_string());
+    }
+
+    let modifiers = if method.receiver.is_some() {
+        "public"
+    } else {
+        "public static"
+    };
+    let output = output
+        .map(|(output, _)| output)
+        .unwrap_or_else(|| "void".to_string());
+    let name = java_name(&method.name, class);
+    let throws = java_throws(method);
+    let body = body.iter().map(|line| format!("        {line}")).join("\n");
+    Ok(format!(
+        r#"
+    {modifiers} {output} {name}({params}){throws} {{
+{body}
+    }}
+"#
+    ))
+}
+
+/// The static native method the macro exports for the method
+fn java_native_method(class: &Class, method: &Method) -> Result<String, Error> {
+    let params = method
+        .receiver
+        .map(|_| format!("{} self", class.name))
+        .into_iter()
+        .chain(
+            method
+                .params
+                .iter()
+                .map(|param| {
+                    Ok(format!(
+                        "{} {}",
+                        java_type(&param.ty)?,
+                        java_name(&param.name, class)
+                    ))
+                })
+                .collect::<Result<Vec<_>, Error>>()?,
+        )
+        .join(", ");
+    let output = match &method.returns {
+        Some(ty) => java_type(ty)?,
+        None => "void".to_string(),
+    };
+    Ok(format!(
+        "    private static native {output} {}({params}){};\n",
+        method.native_name,
+        java_throws(method)
+    ))
+}
+
+/// The java class wrapping the native methods of a resolved `#[java_class]`
+pub fn class_to_java(
+    project_root: &str,
+    class: &Class,
+    rust_lib: &str,
+) -> Result<KotlinClass, Error> {
+    let methods: String = class
+        .methods
+        .iter()
+        .map(|method| java_method(class, method))
+        .collect::<Result<_, Error>>()?;
+    let native_methods: String = class
+        .methods
+        .iter()
+        .map(|method| java_native_method(class, method))
+        .collect::<Result<_, Error>>()?;
+    let Class { package, name, .. } = class;
+    // a returned ByteBuffer points into the rust object, which is only freed once they're all
+    // unreachable
+    let (buffer_fields, close, buffer_methods) = if returns_buffers(class) {
+        (
+            r#"
+    /** Returned ByteBuffers over memory of this object that are still reachable, read by rust */
+    private int buffers;
+    /** Whether {@link #close} waits for the last reachable ByteBuffer to free the rust object */
+    private boolean closePending;"#,
+            r#"
+    /**
+     * Frees the rust object now rather than when this object is garbage collected, or once the
+     * last ByteBuffer it returned is unreachable. A call still running on another thread leaves
+     * it to the garbage collector. Any later method call throws an
+     * {@link IllegalStateException}.
+     */
+    @Override
+    public void close() {
+        handle = 0;
+        synchronized (this) {
+            if (buffers > 0) {
+                closePending = true;
+                return;
+            }
+        }
+        if (cleaner != null) {
+            cleaner.close();
+        }
+    }"#,
+            r#"
+
+    /**
+     * Called by rust with the ByteBuffer it created over this object, while the method returning
+     * it still borrows the object. Views of it like {@code asReadOnlyBuffer()} or {@code slice()}
+     * keep that buffer reachable, so this object is kept alive until it's unreachable
+     */
+    private synchronized void bufferReturned(java.nio.ByteBuffer buffer) {
+        buffers++;
+        Library.CLEANER.register(buffer, this::bufferReleased);
+    }
+
+    /** Runs once a returned ByteBuffer is unreachable, this object is kept alive until then */
+    private void bufferReleased() {
+        synchronized (this) {
+            buffers--;
+            if (buffers > 0 || !closePending) {
+                return;
+            }
+        }
+        if (cleaner != null) {
+            cleaner.close();
+        }
+    }"#,
+        )
+    } else {
+        (
+            "",
+            r#"
+    /**
+     * Frees the rust object now rather than when this object is garbage collected, unless a call
+     * still runs on another thread. Any later method call throws an {@link IllegalStateException}.
+     */
+    @Override
+    public void close() {
+        handle = 0;
+        if (cleaner != null) {
+            cleaner.close();
+        }
+    }"#,
+            "",
+        )
+    };
+    Ok(KotlinClass {
+        path: package.clone(),
+        name: name.clone(),
+        code: format!(
+            r#"
+package {package};
+
+import {project_root}.Library;
+import {project_root}.NativeLoader;
+import java.lang.ref.Cleaner;
+import java.util.concurrent.atomic.AtomicLong;
+
+public final class {name} implements AutoCloseable {{
+    static {{
+        NativeLoader.load("{rust_lib}");
+    }}
+
+    /** Address of the rust object, set by the native methods and zeroed once it's freed */
+    private volatile long handle;
+    private {name}Cleaner cleaner;
+    private Cleaner.Cleanable cleanable;{buffer_fields}
+
+    /** Only the native methods create instances */
+    private {name}() {{}}
+
+    private static final class {name}Cleaner implements Runnable {{
+        private final AtomicLong handle;
+
+        {name}Cleaner(long handle) {{
+            this.handle = new AtomicLong(handle);
+        }}
+
+        /** Stops the rust object from being dropped, once rust has taken ownership of it */
+        void disarm() {{
+            handle.set(0);
+        }}
+
+        /**
+         * Drops the rust object, unless a call on another thread still uses it. It's then left
+         * to the garbage collector, which can only run this once that call returned
+         */
+        void close() {{
+            long handle = this.handle.getAndSet(0);
+            if (handle != 0 && !dropByHandleExtern(handle)) {{
+                this.handle.set(handle);
+            }}
+        }}
+
+        @Override
+        public void run() {{
+            close();
+        }}
+    }}
+{close}
+
+    /** Internal, called by the generated classes on the objects rust returns */
+    public void registerCleaner() {{
+        cleaner = new {name}Cleaner(handle);
+        cleanable = Library.CLEANER.register(this, cleaner);
+    }}
+
+    /**
+     * Internal, called by the generated classes after passing this object by value. Rust zeroes
+     * the handle when it takes ownership
+     */
+    public void releaseIfConsumed() {{
+        if (handle == 0 && cleaner != null) {{
+            cleaner.disarm();
+            cleanable.clean();
+        }}
+    }}{buffer_methods}
+{methods}
+{native_methods}
+    private static native boolean dropByHandleExtern(long handle);
+}}
+"#
+        ),
+    })
+}
+
+/// The java version of [`crate::token_processing::enum_to_kotlin`]. Java 17's sealed classes
+/// aren't needed, the private constructor already keeps other classes from extending it
+pub fn enum_to_java(enum_item: &Enum) -> Result<KotlinClass, Error> {
+    let Enum { package, name, .. } = enum_item;
+    if enum_item.is_fieldless() {
+        let constants: String = enum_item
+            .variants
+            .iter()
+            .map(|variant| format!("    {},\n", variant.name))
+            .collect();
+        return Ok(KotlinClass {
+            path: package.clone(),
+            name: name.clone(),
+            code: format!(
+                r#"
+package {package};
+
+public enum {name} {{
+{constants}}}
+"#
+            ),
+        });
+    }
+    let variants: String = enum_item
+        .variants
+        .iter()
+        .map(|variant| java_variant(name, variant))
+        .collect::<Result<_, Error>>()?;
+    Ok(KotlinClass {
+        path: package.clone(),
+        name: name.clone(),
+        code: format!(
+            r#"
+package {package};
+
+public abstract class {name} {{
+    /** Only the variants extend it */
+    private {name}() {{}}
+{variants}}}
+"#
+        ),
+    })
+}
+
+/// The subclass of a variant of a `#[java_enum]` with fields, with the `equals`, `hashCode` and
+/// `toString` of a kotlin data class. A variant without fields has a single `INSTANCE`
+fn java_variant(enum_name: &str, variant: &Variant) -> Result<String, Error> {
+    let name = &variant.name;
+    if variant.fields.is_empty() {
+        return Ok(format!(
+            r#"
+    public static final class {name} extends {enum_name} {{
+        public static final {name} INSTANCE = new {name}();
+
+        private {name}() {{}}
+
+        @Override
+        public String toString() {{
+            return "{name}";
+        }}
+    }}
+"#
+        ));
+    }
+    let fields = variant
+        .fields
+        .iter()
+        .map(|field| Ok((java_type(&field.ty)?, field.name.as_str())))
+        .collect::<Result<Vec<_>, Error>>()?;
+    let declarations: String = fields
+        .iter()
+        .map(|(ty, field)| format!("        public final {ty} {field};\n"))
+        .collect();
+    let params = fields
+        .iter()
+        .map(|(ty, field)| format!("{ty} {field}"))
+        .join(", ");
+    let assignments: String = fields
+        .iter()
+        .map(|(_, field)| format!("            this.{field} = {field};\n"))
+        .collect();
+    let equals = fields
+        .iter()
+        .map(|(_, field)| format!("java.util.Objects.equals({field}, that.{field})"))
+        .join("\n                && ");
+    let hash = fields.iter().map(|(_, field)| *field).join(", ");
+    let to_string = fields
+        .iter()
+        .map(|(_, field)| format!("{field}=\" + {field} + \""))
+        .join(", ");
+    Ok(format!(
+        r#"
+    public static final class {name} extends {enum_name} {{
+{declarations}
+        public {name}({params}) {{
+{assignments}        }}
+
+        @Override
+        public boolean equals(Object other) {{
+            if (this == other) {{
+                return true;
+            }}
+            if (!(other instanceof {name})) {{
+                return false;
+            }}
+            {name} that = ({name}) other;
+            return {equals};
+        }}
+
+        @Override
+        public int hashCode() {{
+            return java.util.Objects.hash({hash});
+        }}
+
+        @Override
+        public String toString() {{
+            return "{name}({to_string})";
+        }}
+    }}
+"#
+    ))
+}
+
+/// The java exceptions thrown by generated methods in package `path`, when the rust method
+/// returned an `Err` or panicked
+pub fn java_exception_classes(path: &str) -> Vec<KotlinClass> {
+    [
+        (RUST_EXCEPTION, "Exception"),
+        (RUST_PANIC_EXCEPTION, "RuntimeException"),
+    ]
+    .into_iter()
+    .map(|(name, base)| KotlinClass {
+        path: path.into(),
+        name: name.into(),
+        code: format!(
+            r#"
+package {path};
+
+public class {name} extends {base} {{
+    private static final long serialVersionUID = 1L;
+
+    public {name}(String message) {{
+        super(message);
+    }}
+}}
+"#
+        ),
+    })
+    .collect()
+}
+
+/// The `Library` class in `project_root` holding the `Cleaner` shared by all classes
+pub fn java_library_class(project_root: &str) -> KotlinClass {
+    KotlinClass {
+        path: project_root.into(),
+        name: "Library".into(),
+        code: format!(
+            r#"
+package {project_root};
+
+import java.lang.ref.Cleaner;
+
+public final class Library {{
+    public static final Cleaner CLEANER = Cleaner.create();
+
+    private Library() {{}}
+}}
+"#
+        ),
+    }
+}
+
+/// The java version of [`crate::token_processing::native_loader`]
+pub fn java_native_loader(project_root: &str) -> KotlinClass {
+    let env_prefix = project_root.to_uppercase().replace(['.', '-'], "_");
+    KotlinClass {
+        path: project_root.into(),
+        name: "NativeLoader".into(),
+        code: format!(
+            r#"
+package {project_root};
+
+import java.io.File;
+import java.io.IOException;
+import java.io.InputStream;
+import java.io.InputStreamReader;
+import java.io.BufferedReader;
+import java.nio.charset.StandardCharsets;
+import java.nio.file.Files;
+import java.nio.file.Path;
+import java.nio.file.Paths;
+import java.nio.file.StandardCopyOption;
+import java.security.MessageDigest;
+import java.security.NoSuchAlgorithmException;
+import java.util.Collections;
+import java.util.HashSet;
+import java.util.List;
+import java.util.Locale;
+import java.util.Set;
+import java.util.stream.Collectors;
+
+/**
+ * Loads the rust library bundled under {{@code natives/<os>-<arch>/}}, extracting it to a directory
+ * named after its sha-256 so that the extraction of an earlier run is reused.
+ *
+ * <p>For development, point {{@link #LIBRARY_PATH_PROPERTY}} or {{@link #LIBRARY_PATH_ENV}} at a
+ * locally built library to load that instead. Platforms without a bundled library fall back to
+ * {{@code System.loadLibrary}}.
+ */
+public final class NativeLoader {{
+    public static final String LIBRARY_PATH_PROPERTY = "{project_root}.library.path";
+    public static final String LIBRARY_PATH_ENV = "{env_prefix}_LIBRARY_PATH";
+    /** Directory to extract the library to, defaults to a directory in {{@code java.io.tmpdir}} */
+    public static final String CACHE_DIR_PROPERTY = "{project_root}.cache.dir";
+
+    private static final Set<String> loaded = new HashSet<>();
+
+    private NativeLoader() {{}}
+
+    /** The {{@code <os>-<arch>}} directories under {{@code natives/}} the library was built for */
+    public static List<String> supportedPlatforms() {{
+        try (InputStream platforms = NativeLoader.class.getResourceAsStream("/natives/platforms")) {{
+            if (platforms == null) {{
+                return Collections.emptyList();
+            }}
+            BufferedReader reader =
+                new BufferedReader(new InputStreamReader(platforms, StandardCharsets.UTF_8));
+            return reader.lines().filter(line -> !line.isBlank()).collect(Collectors.toList());
+        }} catch (IOException e) {{
+            return Collections.emptyList();
+        }}
+    }}
+
+    /** The platform of the running JVM, named like the directories under {{@code natives/}} */
+    public static String platform() {{
+        String osName = System.getProperty("os.name").toLowerCase(Locale.ROOT);
+        String os;
+        if (osName.startsWith("linux")) {{
+            os = "linux";
+        }} else if (osName.startsWith("mac") || osName.startsWith("darwin")) {{
+            os = "macos";
+        }} else if (osName.startsWith("windows")) {{
+            os = "windows";
+        }} else {{
+            os = osName.replace(' ', '_');
+        }}
+        String arch = System.getProperty("os.arch").toLowerCase(Locale.ROOT);
+        switch (arch) {{
+            case "amd64":
+            case "x86_64":
+            case "x64":
+                arch = "x86_64";
+                break;
+            case "aarch64":
+            case "arm64":
+                arch = "aarch64";
+                break;
+            case "x86":
+            case "i386":
+            case "i486":
+            case "i586":
+            case "i686":
+                arch = "x86";
+                break;
+            default:
+                break;
+        }}
+        return os + "-" + arch;
+    }}
+
+    /** Loads the native library {{@code name}}, once */
+    public static synchronized void load(String name) {{
+        if (loaded.contains(name)) {{
+            return;
+        }}
+        String override = System.getProperty(LIBRARY_PATH_PROPERTY);
+        if (override == null) {{
+            override = System.getenv(LIBRARY_PATH_ENV);
+        }}
+        if (override != null) {{
+            System.load(new File(override).getAbsolutePath());
+        }} else {{
+            String fileName = System.mapLibraryName(name);
+            byte[] bytes = readResource("/natives/" + platform() + "/" + fileName);
+            if (bytes != null) {{
+                System.load(extract(bytes, fileName).toString());
+            }} else {{
+                try {{
+                    System.loadLibrary(name);
+                }} catch (UnsatisfiedLinkError e) {{
+                    throw new UnsatisfiedLinkError(
+                        name + " was not built for " + platform() + ", only for "
+                            + String.join(", ", supportedPlatforms())
+                            + ", and isn't on java.library.path either: " + e.getMessage());
+                }}
+            }}
+        }}
+        loaded.add(name);
+    }}
+
+    private static byte[] readResource(String resource) {{
+        try (InputStream stream = NativeLoader.class.getResourceAsStream(resource)) {{
+            return stream == null ? null : stream.readAllBytes();
+        }} catch (IOException e) {{
+            throw new UnsatisfiedLinkError("Failed to read " + resource + ": " + e.getMessage());
+        }}
+    }}
+
+    private static Path extract(byte[] bytes, String fileName) {{
+        try {{
+            StringBuilder hash = new StringBuilder();
+            for (byte b : MessageDigest.getInstance("SHA-256").digest(bytes)) {{
+                hash.append(String.format("%02x", b));
+            }}
+            String cacheDirProperty = System.getProperty(CACHE_DIR_PROPERTY);
+            Path cacheDir = cacheDirProperty != null
+                ? Paths.get(cacheDirProperty)
+                : Paths.get(System.getProperty("java.io.tmpdir"), "{project_root}-natives");
+            Path dir = cacheDir.resolve(hash.toString());
+            Path library = dir.resolve(fileName);
+            if (Files.isRegularFile(library) && Files.size(library) == bytes.length) {{
+                return library;
+            }}
+            Files.createDirectories(dir);
+            // written next to the library and moved in place, so no other JVM sees a partial file
+            Path partial = Files.createTempFile(dir, fileName, ".partial");
+            try {{
+                Files.write(partial, bytes);
+                Files.move(partial, library, StandardCopyOption.ATOMIC_MOVE);
+            }} catch (IOException e) {{
+                // another JVM may have extracted it first
+                if (!Files.isRegularFile(library) || Files.size(library) != bytes.length) {{
+                    throw e;
+                }}
+            }} finally {{
+                Files.deleteIfExists(partial);
+            }}
+            return library;
+        }} catch (IOException | NoSuchAlgorithmException e) {{
+            throw new UnsatisfiedLinkError("Failed to extract " + fileName + ": " + e.getMessage());
+        }}
+    }}
+}}
+"#
+        ),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use quote::quote;
+
+    use super::{class_to_java, enum_to_java};
+    use crate::token_processing::{
+        fill_lookup, module_classes, module_enums, PackageLookup, RustModule,
+    };
+
+    #[test]
+    fn test_java_class() {
+        let stream = quote! {
+            #[java_class("beep.bop")]
+            impl SomeStruct {
+                fn new(size: Option<i32>) -> Result<Self, String> {
+                    todo!()
+                }
+                fn merge(self, other: SomeStruct, names: Vec<String>) -> Option<SomeStruct> {
+                    todo!()
+                }
+                fn len(&self) -> i64 {
+                    todo!()
+                }
+                fn view(&self) -> Option<DirectBuffer<'_>> {
+                    todo!()
+                }
+            }
+        };
+        let module = RustModule::parse("crate", &stream.to_string()).expect("valid rust");
+        let mut lookup = PackageLookup::default();
+        fill_lookup(&module, &mut lookup).expect("Okay");
+        let classes = module_classes(&module, &lookup).expect("Not OK");
+        let class = class_to_java("beep.boop", &classes[0], "lets_go").expect("Not OK");
+        let code = &class.code;
+        assert_eq!(class.path, "beep.bop");
+        assert!(code.contains("public final class SomeStruct implements AutoCloseable {"));
+        assert!(code.contains("NativeLoader.load(\"lets_go\");"));
+        assert!(code.contains(
+            "public static beep.bop.SomeStruct create(Integer size) throws RustException {"
+        ));
+        assert!(code.contains(
+            "private static native beep.bop.SomeStruct newExtern(Integer size) throws RustException;"
+        ));
+        assert!(code.contains(
+            "public beep.bop.SomeStruct merge(beep.bop.SomeStruct other, String[] names) {"
+        ));
+        assert!(code.contains(
+            "} finally {\n            releaseIfConsumed();\n            other.releaseIfConsumed();\n        }"
+        ));
+        assert!(code.contains("if (obj != null) obj.registerCleaner();"));
+        assert!(code.contains("private volatile long handle;"));
+        assert!(code.contains("if (handle != 0 && !dropByHandleExtern(handle)) {"));
+        assert!(code.contains("public long len() {\n        return lenExtern(this);\n    }"));
+        assert!(code.contains("private static native long lenExtern(SomeStruct self);"));
+        assert!(code.contains("return viewExtern(this);"));
+        assert!(code.contains("Library.CLEANER.register(buffer, this::bufferReleased);"));
+    }
+
+    #[test]
+    fn test_java_enum() {
+        let stream = quote! {
+            #[java_enum("beep.bop")]
+            enum PaddingDirection {
+                Left,
+                Right,
+            }
+
+            #[java_enum("beep.bop")]
+            enum Truncation {
+                LongestFirst { max_length: i64, direction: Option<PaddingDirection> },
+                Off,
+            }
+        };
+        let module = RustModule::parse("crate", &stream.to_string()).expect("valid rust");
+        let mut lookup = PackageLookup::default();
+        fill_lookup(&module, &mut lookup).expect("Okay");
+        let enums = module_enums(&module, &lookup).expect("Not OK");
+
+        let code = enum_to_java(&enums[0]).expect("Not OK").code;
+        assert!(code.contains("public enum PaddingDirection {\n    LEFT,\n    RIGHT,\n}"));
+
+        let code = enum_to_java(&enums[1]).expect("Not OK").code;
+        assert!(code.contains("public abstract class Truncation {"));
+        assert!(code.contains("private Truncation() {}"));
+        assert!(code.contains("public static final class LongestFirst extends Truncation {"));
+        assert!(code.contains("public final long maxLength;"));
+        assert!(code.contains("public final beep.bop.PaddingDirection direction;"));
+        assert!(code.contains(
+            "public LongestFirst(long maxLength, beep.bop.PaddingDirection direction) {"
+        ));
+        assert!(code.contains("return java.util.Objects.hash(maxLength, direction);"));
+        assert!(code.contains(
+            "return \"LongestFirst(maxLength=\" + maxLength + \", direction=\" + direction + \")\";"
+        ));
+        assert!(code.contains("public static final Off INSTANCE = new Off();"));
+    }
+}
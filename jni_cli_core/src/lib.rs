@@ -1,16 +1,39 @@
-use std::{sync::RwLock};
+use std::{
+    any::Any,
+    backtrace::{Backtrace, BacktraceStatus},
+    borrow::Cow,
+    cell::RefCell,
+    ffi::CString,
+    fmt::Display,
+    iter::once,
+    marker::PhantomData,
+    panic::{self, AssertUnwindSafe},
+    ptr::NonNull,
+    sync::Once,
+};
 
 use jni::{
     descriptors::Desc,
-    objects::{JByteArray, JClass, JObject, JObjectArray, JString, JValue},
-    sys::{jboolean, jdouble, jfloat, jint, jlong},
+    objects::{
+        JByteBuffer, JClass, JObject, JObjectArray, JPrimitiveArray, JString, JValue, JValueOwned,
+        TypeArray,
+    },
+    strings::JavaStr,
+    sys::{jboolean, jbyte, jchar, jdouble, jfloat, jint, jlong, jshort, jsize},
     JNIEnv,
 };
 pub use jni_fn::jni_fn;
 
 pub(crate) type Result<T> = std::result::Result<T, Error>;
+pub mod java;
+pub mod manifest;
+pub mod mutf8;
+pub mod storage;
 pub mod token_processing;
 
+use manifest::{Primitive, PrimitiveArray};
+use storage::{AccessError, Storage, StorageMut};
+
 #[derive(Debug, thiserror::Error)]
 pub enum Error {
     #[error("Jni Error {0}")]
@@ -22,9 +45,22 @@ pub enum Error {
         context_message: String,
         err: Box<Error>,
     },
+    /// An error that should be raised in the JVM as an instance of `class`
+    #[error("{message}")]
+    Exception {
+        class: &'static str,
+        message: String,
+    },
 }
 
 impl Error {
+    pub fn exception(class: &'static str, message: impl Display) -> Error {
+        Error::Exception {
+            class,
+            message: message.to_string(),
+        }
+    }
+
     fn context(self, message: String) -> Error {
         match self {
             Error::Contextual {
@@ -58,6 +94,184 @@ where
     }
 }
 
+/// Value handed back to the JVM from an extern fn that has thrown an exception.
+/// The JVM never looks at it, but the function still has to return something
+pub trait JNull {
+    fn null() -> Self;
+}
+
+macro_rules! j_null_primitive {
+    ($($ty:ty),*) => {
+        $(impl JNull for $ty {
+            fn null() -> Self {
+                Self::default()
+            }
+        })*
+    };
+}
+
+j_null_primitive!(
+    (),
+    jboolean,
+    jbyte,
+    jchar,
+    jshort,
+    jint,
+    jlong,
+    jfloat,
+    jdouble
+);
+
+impl<'a> JNull for JObject<'a> {
+    fn null() -> Self {
+        JObject::null()
+    }
+}
+
+impl<'a> JNull for JString<'a> {
+    fn null() -> Self {
+        JObject::null().into()
+    }
+}
+
+impl<'a> JNull for JObjectArray<'a> {
+    fn null() -> Self {
+        JObject::null().into()
+    }
+}
+
+impl<'a> JNull for JByteBuffer<'a> {
+    fn null() -> Self {
+        JObject::null().into()
+    }
+}
+
+impl<'a, T: TypeArray> JNull for JPrimitiveArray<'a, T> {
+    fn null() -> Self {
+        JObject::null().into()
+    }
+}
+
+/// Returns the value of a generated extern fn, throwing `result`'s error in the JVM if
+/// there is one. A java exception that is already pending is left to propagate as is.
+pub fn unwrap_or_throw<T: JNull>(env: &mut JNIEnv<'_>, result: Result<T>) -> T {
+    match result {
+        Ok(t) => t,
+        Err(err) => {
+            throw(env, err);
+            T::null()
+        }
+    }
+}
+
+thread_local! {
+    static PANIC_BACKTRACE: RefCell<Option<Backtrace>> = const { RefCell::new(None) };
+}
+
+static PANIC_HOOK: Once = Once::new();
+
+/// Runs the body of a generated extern fn, turning a panic into an [`Error::Exception`] of
+/// class `panic_class` instead of unwinding into the JVM. The rust backtrace is added to
+/// the message when enabled through `RUST_BACKTRACE` or `RUST_LIB_BACKTRACE`.
+pub fn catch_unwind<T, F: FnOnce() -> Result<T>>(panic_class: &'static str, f: F) -> Result<T> {
+    PANIC_HOOK.call_once(|| {
+        let default_hook = panic::take_hook();
+        panic::set_hook(Box::new(move |info| {
+            PANIC_BACKTRACE.with(|backtrace| *backtrace.borrow_mut() = Some(Backtrace::capture()));
+            default_hook(info);
+        }))
+    });
+    panic::catch_unwind(AssertUnwindSafe(f)).unwrap_or_else(|payload| {
+        let backtrace = PANIC_BACKTRACE.with(|backtrace| backtrace.borrow_mut().take());
+        Err(Error::exception(
+            panic_class,
+            panic_message(payload.as_ref(), backtrace),
+        ))
+    })
+}
+
+fn panic_message(payload: &(dyn Any + Send), backtrace: Option<Backtrace>) -> String {
+    let message = if let Some(message) = payload.downcast_ref::<&str>() {
+        message
+    } else if let Some(message) = payload.downcast_ref::<String>() {
+        message.as_str()
+    } else {
+        "Box<dyn Any>"
+    };
+    match backtrace {
+        Some(backtrace) if backtrace.status() == BacktraceStatus::Captured => {
+            format!("Rust panic: {message}\n\nRust backtrace:\n{backtrace}")
+        }
+        _ => format!("Rust panic: {message}"),
+    }
+}
+
+fn throw(env: &mut JNIEnv<'_>, err: Error) {
+    if env.exception_check().unwrap_or(false) {
+        return;
+    }
+    let (class, message) = match err {
+        Error::Exception { class, message } => (class, message),
+        err => ("java/lang/RuntimeException", err.to_string()),
+    };
+    // if we can't even throw there is nobody left to report the error to
+    let _ = env.throw_new(class, message);
+}
+
+thread_local! {
+    /// Objects borrowed by generated calls on this thread, with a pointer to the value for
+    /// shared borrows and `None` for mutable ones
+    static BORROWS: RefCell<Vec<(jlong, Option<usize>)>> = const { RefCell::new(Vec::new()) };
+}
+
+fn current_borrow(handle: jlong) -> Option<Option<usize>> {
+    BORROWS.with(|borrows| {
+        borrows
+            .borrow()
+            .iter()
+            .find(|(borrowed, _)| *borrowed == handle)
+            .map(|(_, ptr)| *ptr)
+    })
+}
+
+/// Records a borrow in [`BORROWS`] for as long as it lives, including while unwinding
+struct BorrowGuard;
+
+impl BorrowGuard {
+    fn new(handle: jlong, ptr: Option<usize>) -> BorrowGuard {
+        BORROWS.with(|borrows| borrows.borrow_mut().push((handle, ptr)));
+        BorrowGuard
+    }
+}
+
+impl Drop for BorrowGuard {
+    fn drop(&mut self) {
+        BORROWS.with(|borrows| borrows.borrow_mut().pop());
+    }
+}
+
+thread_local! {
+    /// Objects a generated call is about to borrow, which can't be taken by value meanwhile
+    static RESERVED: RefCell<Vec<jlong>> = const { RefCell::new(Vec::new()) };
+}
+
+/// Keeps the object behind a handle from being taken by value until it is dropped, see
+/// [`reserve`]
+pub struct Reservation(());
+
+/// Reserves an object passed by reference to a generated call, so that passing the same object
+/// by value to that call is reported instead of freeing it while it is still borrowed
+pub fn reserve(handle: jlong) -> Reservation {
+    RESERVED.with(|reserved| reserved.borrow_mut().push(handle));
+    Reservation(())
+}
+
+impl Drop for Reservation {
+    fn drop(&mut self) {
+        RESERVED.with(|reserved| reserved.borrow_mut().pop());
+    }
+}
+
 /// # Safety
 /// a type that implements JavaClass, must be dropped by the java garbage collector
 /// with a cleaner
@@ -67,7 +281,15 @@ where
 {
     const LOC: &'static str;
     const PATH: &'static str;
+    /// Synchronization of the value behind the java object, see [`storage`]
+    type Storage: Storage<Self>;
+    /// Whether methods return [`DirectBuffer`]s over memory of the value, which the generated
+    /// class counts in its `buffers` field
+    const RETURNS_BUFFERS: bool = false;
 
+    /// # Safety
+    /// the returned object owns the boxed value through its `handle` field, which must
+    /// eventually be passed to [`JavaClass::drop_by_handle`] exactly once
     unsafe fn new_from_rust_type<'local>(
         self,
         env: &mut JNIEnv<'local>,
@@ -78,7 +300,7 @@ where
             .new_object(&class, "()V", &[])
             .context(format!("failed to instantiate class {class}"))?;
 
-        let boxed = Box::new(RwLock::new(self));
+        let boxed = Box::new(Self::Storage::new(self));
 
         let handle = Box::into_raw(boxed) as jlong;
 
@@ -90,29 +312,163 @@ where
         Ok(return_obj)
     }
 
-    unsafe fn rust_type_from_handle(handle: jlong) -> Box<RwLock<Self>> {
-        unsafe { Box::from_raw(handle as *mut RwLock<Self>) }
+    /// # Safety
+    /// `handle` must have been created by [`JavaClass::new_from_rust_type`] and not yet dropped
+    unsafe fn rust_type_from_handle(handle: jlong) -> Box<Self::Storage> {
+        unsafe { Box::from_raw(handle as *mut Self::Storage) }
+    }
+
+    /// Borrows the value behind `handle` without taking ownership, so that the value
+    /// survives `f` panicking
+    ///
+    /// # Safety
+    /// `handle` must have been created by [`JavaClass::new_from_rust_type`] and not yet dropped
+    unsafe fn rust_type_ref_from_handle<'a>(handle: jlong) -> &'a Self::Storage {
+        unsafe { &*(handle as *const Self::Storage) }
     }
 
-    fn use_shared<T, F: FnOnce(&Self) -> T>(handle: jlong, f: F) -> T {
+    /// Reads the `handle` field of a java object of this class
+    fn handle_from_object(env: &mut JNIEnv<'_>, obj: &JObject<'_>) -> Result<jlong> {
+        if obj.is_null() {
+            return Err(Error::exception(
+                "java/lang/NullPointerException",
+                format!("{} is null", Self::LOC),
+            ));
+        }
+        let handle = env
+            .get_field(obj, "handle", "J")
+            .and_then(|handle| handle.j())
+            .context(format!(
+                "Failed to get handle pointer for java object: {}",
+                Self::PATH
+            ))?;
+        if handle == 0 {
+            return Err(Error::exception(
+                "java/lang/IllegalStateException",
+                format!("{} has already been closed", Self::LOC),
+            ));
+        }
+        Ok(handle)
+    }
+
+    /// Takes ownership of the value behind a java object of this class, zeroing its handle
+    /// so that the java object can no longer be used
+    fn take_from_object(env: &mut JNIEnv<'_>, obj: &JObject<'_>) -> Result<Self> {
+        let handle = Self::handle_from_object(env, obj)?;
+        let reserved = RESERVED.with(|reserved| reserved.borrow().contains(&handle));
+        if reserved || current_borrow(handle).is_some() {
+            return Err(Self::already_borrowed());
+        }
+        // zeroing the handle while no other thread borrows the value keeps them from using it
+        // after it is freed, later calls see the zeroed handle instead. Buffers are counted while
+        // the value is borrowed, so none can be returned meanwhile either
+        let rust_type = unsafe { Self::rust_type_ref_from_handle(handle) };
+        rust_type
+            .try_exclusive(|| {
+                if Self::RETURNS_BUFFERS && buffer_count(env, obj)? > 0 {
+                    return Err(Error::exception(
+                        "java/lang/IllegalStateException",
+                        format!(
+                            "{} can't be taken by value while a ByteBuffer it returned is reachable",
+                            Self::LOC
+                        ),
+                    ));
+                }
+                env.set_field(obj, "handle", "J", JValue::Long(0))
+                    .context(format!(
+                        "Failed to reset handle pointer for java object: {}",
+                        Self::PATH
+                    ))
+            })
+            .map_err(Self::access_error)??;
         let rust_type = unsafe { Self::rust_type_from_handle(handle) };
-        let t = f(&rust_type.read().expect("Failed to readRwLock"));
+        rust_type.into_inner().map_err(Self::access_error)
+    }
+
+    /// Borrows the value behind `handle`. Borrowing an object that is already borrowed
+    /// further up the stack is fine as long as neither borrow is mutable
+    fn use_shared<T, F: FnOnce(&Self) -> Result<T>>(handle: jlong, f: F) -> Result<T> {
+        match current_borrow(handle) {
+            // locking again could deadlock, the existing lock guarantees no writers anyway
+            Some(Some(ptr)) => return f(unsafe { &*(ptr as *const Self) }),
+            Some(None) => return Err(Self::already_borrowed()),
+            None => {}
+        }
         // garbage collector has to clean up
-        std::mem::forget(rust_type);
-        t
+        let rust_type = unsafe { Self::rust_type_ref_from_handle(handle) };
+        rust_type
+            .read(|value| {
+                let _borrow = BorrowGuard::new(handle, Some(value as *const Self as usize));
+                f(value)
+            })
+            .map_err(Self::access_error)?
     }
 
-    fn use_mut<T, F: FnOnce(&mut Self) -> T>(handel: jlong, f: F) -> T {
-        let rust_type = unsafe { Self::rust_type_from_handle(handel) };
-        let t = f(&mut *rust_type.write().expect("Failed to lock RwLock"));
+    fn use_mut<T, F: FnOnce(&mut Self) -> Result<T>>(handle: jlong, f: F) -> Result<T>
+    where
+        Self::Storage: StorageMut<Self>,
+    {
+        if current_borrow(handle).is_some() {
+            return Err(Self::already_borrowed());
+        }
         // garbage collector has to clean up
-        std::mem::forget(rust_type);
-        t
+        let rust_type = unsafe { Self::rust_type_ref_from_handle(handle) };
+        rust_type
+            .write(|value| {
+                let _borrow = BorrowGuard::new(handle, None);
+                f(value)
+            })
+            .map_err(Self::access_error)?
     }
-    unsafe fn drop_by_handle(handle: jlong) {
+
+    fn access_error(err: AccessError) -> Error {
+        match err {
+            AccessError::Poisoned => Self::poisoned(),
+            AccessError::WrongThread => Error::exception(
+                "java/lang/IllegalStateException",
+                format!(
+                    "{} can only be used from the thread that created it",
+                    Self::LOC
+                ),
+            ),
+            AccessError::Borrowed => Self::already_borrowed(),
+        }
+    }
+
+    fn already_borrowed() -> Error {
+        Error::exception(
+            "java/lang/IllegalArgumentException",
+            format!(
+                "{} is already in use, the same object can't be passed more than once when one of \
+                 its uses is mutable or takes ownership, nor be taken while another thread uses it",
+                Self::LOC
+            ),
+        )
+    }
+
+    fn poisoned() -> Error {
+        Error::exception(
+            "java/lang/IllegalStateException",
+            format!("{} was poisoned by an earlier panic", Self::LOC),
+        )
+    }
+
+    /// Frees the value behind `handle`, or returns `false` without touching it while a call on
+    /// another thread still borrows it
+    ///
+    /// # Safety
+    /// `handle` must have been created by [`JavaClass::new_from_rust_type`] and must not be
+    /// used again once this returns `true`
+    unsafe fn drop_by_handle(handle: jlong) -> bool {
+        let rust_type = unsafe { Self::rust_type_ref_from_handle(handle) };
+        // a poisoned value can still be dropped, a thread confined one is leaked on other threads
+        if rust_type.try_exclusive(|| ()) == Err(AccessError::Borrowed) {
+            return false;
+        }
         unsafe {
             let _ = Self::rust_type_from_handle(handle);
         }
+        true
     }
 }
 
@@ -136,84 +492,232 @@ where
     }
 }
 
+impl<T> PrimitiveJType for T
+where
+    T: JavaClass,
+{
+    fn from_j_type<'env>(env: &mut JNIEnv<'env>, j_type: Self::JType<'env>) -> Result<Self> {
+        T::take_from_object(env, &j_type)
+    }
+}
+
+impl JType for () {
+    type JType<'a> = ();
+
+    fn to_j_type<'env>(self, _env: &mut JNIEnv<'env>) -> Result<Self::JType<'env>> {
+        Ok(())
+    }
+}
+
 impl JType for String {
     type JType<'a> = JString<'a>;
 
     fn to_j_type<'env>(self, env: &mut JNIEnv<'env>) -> Result<Self::JType<'env>> {
-        Ok(env.new_string(self)?)
+        let chars = CString::new(mutf8::encode(&self).into_owned())
+            .expect("modified UTF-8 has no zero bytes");
+        let raw = env.get_raw();
+        let new_string = unsafe { (**raw).NewStringUTF }
+            .ok_or(jni::errors::Error::JNIEnvMethodNotFound("NewStringUTF"))?;
+        let string = unsafe { new_string(raw, chars.as_ptr()) };
+        if string.is_null() {
+            // an OutOfMemoryError is pending
+            return Err(jni::errors::Error::JavaException.into());
+        }
+        Ok(unsafe { JString::from_raw(string) })
     }
 }
 
 impl PrimitiveJType for String {
     fn from_j_type<'env>(env: &mut JNIEnv<'env>, j_type: Self::JType<'env>) -> Result<Self> {
-        Ok(env.get_string(&j_type)?.into())
+        Ok(mutf8::decode(env.get_string(&j_type)?.to_bytes()).into_owned())
     }
 }
 
-impl JType for i64 {
-    type JType<'a> = jlong;
+/// The characters of a java string passed as a `&str` or `Cow<str>` argument, borrowed for the
+/// call. They are only copied into a `String` if their modified UTF-8 isn't valid UTF-8 too,
+/// which takes a `\0` or a character outside the basic multilingual plane
+pub struct StrArg<'local, 'other_local: 'obj_ref, 'obj_ref> {
+    chars: JavaStr<'local, 'other_local, 'obj_ref>,
+}
 
-    fn to_j_type<'env>(self, _env: &mut JNIEnv<'env>) -> Result<Self::JType<'env>> {
-        Ok(self)
+impl<'local, 'other_local: 'obj_ref, 'obj_ref> StrArg<'local, 'other_local, 'obj_ref> {
+    pub fn new(env: &JNIEnv<'local>, string: &'obj_ref JString<'other_local>) -> Result<Self> {
+        if string.is_null() {
+            return Err(Error::exception(
+                "java/lang/NullPointerException",
+                "The String argument is null",
+            ));
+        }
+        // the JNI signature of the native method already guarantees a java.lang.String
+        let chars = unsafe { env.get_string_unchecked(string)? };
+        Ok(StrArg { chars })
     }
-}
 
-impl PrimitiveJType for i64 {
-    fn from_j_type<'env>(_env: &mut JNIEnv<'env>, j_type: Self::JType<'env>) -> Result<Self> {
-        Ok(j_type)
+    pub fn to_cow(&self) -> Cow<'_, str> {
+        mutf8::decode(self.chars.to_bytes())
     }
 }
 
-impl JType for bool {
-    type JType<'a> = jboolean;
+/// The java primitives and the rust types passed as them, with their kotlin and java types,
+/// JNI descriptor, boxed class and unboxing method. The `JType` impls, the boxing of `Option`s
+/// and the types of the generated kotlin and java code ([`PRIMITIVES`]) all come from this
+/// table, so that they can't disagree
+macro_rules! java_primitives {
+    ($callback:ident) => {
+        $callback!(
+            bool => jboolean, "Boolean", "boolean", "Z", "java/lang/Boolean", "booleanValue";
+            i8 => jbyte, "Byte", "byte", "B", "java/lang/Byte", "byteValue";
+            i16 => jshort, "Short", "short", "S", "java/lang/Short", "shortValue";
+            u16 => jchar, "Char", "char", "C", "java/lang/Character", "charValue";
+            char => jchar, "Char", "char", "C", "java/lang/Character", "charValue";
+            i32 => jint, "Int", "int", "I", "java/lang/Integer", "intValue";
+            i64 => jlong, "Long", "long", "J", "java/lang/Long", "longValue";
+            f32 => jfloat, "Float", "float", "F", "java/lang/Float", "floatValue";
+            f64 => jdouble, "Double", "double", "D", "java/lang/Double", "doubleValue"
+        );
+    };
+}
 
-    fn to_j_type<'env>(self, _env: &mut JNIEnv<'env>) -> Result<Self::JType<'env>> {
-        Ok(self as u8)
-    }
+/// The java primitive arrays and the `Vec`s passed as them, with the element types, kotlin
+/// and java types, JNI descriptor and the [`JNIEnv`] methods creating and copying them. See
+/// [`PRIMITIVE_ARRAYS`]
+macro_rules! java_primitive_arrays {
+    ($callback:ident) => {
+        $callback!(
+            bool => jboolean, "BooleanArray", "boolean[]", "[Z",
+                new_boolean_array, get_boolean_array_region, set_boolean_array_region;
+            i8 => jbyte, "ByteArray", "byte[]", "[B",
+                new_byte_array, get_byte_array_region, set_byte_array_region;
+            u8 => jbyte, "ByteArray", "byte[]", "[B",
+                new_byte_array, get_byte_array_region, set_byte_array_region;
+            i16 => jshort, "ShortArray", "short[]", "[S",
+                new_short_array, get_short_array_region, set_short_array_region;
+            u16 => jchar, "CharArray", "char[]", "[C",
+                new_char_array, get_char_array_region, set_char_array_region;
+            i32 => jint, "IntArray", "int[]", "[I",
+                new_int_array, get_int_array_region, set_int_array_region;
+            i64 => jlong, "LongArray", "long[]", "[J",
+                new_long_array, get_long_array_region, set_long_array_region;
+            f32 => jfloat, "FloatArray", "float[]", "[F",
+                new_float_array, get_float_array_region, set_float_array_region;
+            f64 => jdouble, "DoubleArray", "double[]", "[D",
+                new_double_array, get_double_array_region, set_double_array_region
+        );
+    };
 }
 
-impl JType for i32 {
-    type JType<'a> = jint;
+macro_rules! primitive_table {
+    ($($ty:ty => $jni:ty, $kotlin:literal, $java:literal, $descriptor:literal, $boxed:literal, $unbox:literal);*) => {
+        /// The rust types passed as java primitives
+        pub const PRIMITIVES: &[Primitive] = &[$(Primitive {
+            rust: stringify!($ty),
+            kotlin: $kotlin,
+            java: $java,
+            descriptor: $descriptor,
+            boxed: $boxed,
+        }),*];
+    };
+}
 
-    fn to_j_type<'env>(self, _env: &mut JNIEnv<'env>) -> Result<Self::JType<'env>> {
-        Ok(self)
-    }
+java_primitives!(primitive_table);
+
+macro_rules! primitive_array_table {
+    ($($ty:ty => $jni:ty, $kotlin:literal, $java:literal, $descriptor:literal, $new:ident, $get:ident, $set:ident);*) => {
+        /// The `Vec`s passed as java primitive arrays
+        pub const PRIMITIVE_ARRAYS: &[PrimitiveArray] = &[$(PrimitiveArray {
+            element: stringify!($ty),
+            kotlin: $kotlin,
+            java: $java,
+            descriptor: $descriptor,
+        }),*];
+    };
 }
 
-impl PrimitiveJType for i32 {
-    fn from_j_type<'env>(_env: &mut JNIEnv<'env>, j_type: Self::JType<'env>) -> Result<Self> {
-        Ok(j_type)
-    }
+java_primitive_arrays!(primitive_array_table);
+
+/// A rust type passed to the JVM as the java primitive `Jni`
+pub trait JPrimitive: Sized {
+    type Jni: Copy;
+    fn into_jni(self) -> Result<Self::Jni>;
+    fn from_jni(value: Self::Jni) -> Result<Self>;
 }
 
-impl JType for f32 {
-    type JType<'a> = jfloat;
+macro_rules! same_primitive {
+    ($($ty:ty),*) => {
+        $(impl JPrimitive for $ty {
+            type Jni = $ty;
 
-    fn to_j_type<'env>(self, _env: &mut JNIEnv<'env>) -> Result<Self::JType<'env>> {
-        Ok(self)
-    }
+            fn into_jni(self) -> Result<Self::Jni> {
+                Ok(self)
+            }
+
+            fn from_jni(value: Self::Jni) -> Result<Self> {
+                Ok(value)
+            }
+        })*
+    };
 }
 
-impl PrimitiveJType for f32 {
-    fn from_j_type<'env>(_env: &mut JNIEnv<'env>, j_type: Self::JType<'env>) -> Result<Self> {
-        Ok(j_type)
+same_primitive!(i8, i16, u16, i32, i64, f32, f64);
+
+impl JPrimitive for bool {
+    type Jni = jboolean;
+
+    fn into_jni(self) -> Result<Self::Jni> {
+        Ok(self.into())
+    }
+
+    fn from_jni(value: Self::Jni) -> Result<Self> {
+        Ok(value != 0)
     }
 }
 
-impl JType for f64 {
-    type JType<'a> = jdouble;
+/// A java `char` is a UTF-16 code unit, so only the characters of the basic multilingual plane
+/// fit and surrogates aren't characters
+impl JPrimitive for char {
+    type Jni = jchar;
 
-    fn to_j_type<'env>(self, _env: &mut JNIEnv<'env>) -> Result<Self::JType<'env>> {
-        Ok(self)
+    fn into_jni(self) -> Result<Self::Jni> {
+        jchar::try_from(u32::from(self)).map_err(|_| {
+            Error::exception(
+                "java/lang/IllegalArgumentException",
+                format!(
+                    "{self:?} is outside the basic multilingual plane and can't be a java char"
+                ),
+            )
+        })
     }
-}
 
-impl PrimitiveJType for f64 {
-    fn from_j_type<'env>(_env: &mut JNIEnv<'env>, j_type: Self::JType<'env>) -> Result<Self> {
-        Ok(j_type)
+    fn from_jni(value: Self::Jni) -> Result<Self> {
+        char::from_u32(value.into()).ok_or_else(|| {
+            Error::exception(
+                "java/lang/IllegalArgumentException",
+                format!("The surrogate {value:#x} isn't a rust char"),
+            )
+        })
     }
 }
 
+macro_rules! primitive_j_type {
+    ($($ty:ty => $jni:ty, $kotlin:literal, $java:literal, $descriptor:literal, $boxed:literal, $unbox:literal);*) => {
+        $(impl JType for $ty {
+            type JType<'a> = $jni;
+
+            fn to_j_type<'env>(self, _env: &mut JNIEnv<'env>) -> Result<Self::JType<'env>> {
+                self.into_jni()
+            }
+        }
+
+        impl PrimitiveJType for $ty {
+            fn from_j_type<'env>(_env: &mut JNIEnv<'env>, j_type: Self::JType<'env>) -> Result<Self> {
+                Self::from_jni(j_type)
+            }
+        })*
+    };
+}
+
+java_primitives!(primitive_j_type);
+
 impl JType for Vec<String> {
     type JType<'a> = JObjectArray<'a>;
 
@@ -237,28 +741,372 @@ impl PrimitiveJType for Vec<String> {
                 let j_obj = env.get_object_array_element(&j_type, i)?;
                 let j_str: JString<'env> = j_obj.into();
                 let j_string = env.get_string(&j_str)?;
-                Ok(j_string.to_str()?.to_string())
+                Ok(mutf8::decode(j_string.to_bytes()).into_owned())
             })
             .collect()
     }
 }
 
-impl JType for Vec<u8> {
-    type JType<'a> = JByteArray<'a>;
+/// A rust type stored in java primitive arrays of `Jni`
+trait ArrayElement: Sized {
+    type Jni: Copy + Default;
+    fn to_jni_array(values: Vec<Self>) -> Vec<Self::Jni>;
+    fn from_jni_array(values: Vec<Self::Jni>) -> Vec<Self>;
+}
 
-    fn to_j_type<'env>(self, env: &mut JNIEnv<'env>) -> Result<Self::JType<'env>> {
-        let mut object_array = env.new_byte_array(self.len() as i32)?;
-        for (i, val) in self.into_iter().enumerate() {
-            let arr = [val as i8];
-            env.set_byte_array_region(&mut object_array, i as i32, &arr)?;
+macro_rules! same_array_element {
+    ($($ty:ty),*) => {
+        $(impl ArrayElement for $ty {
+            type Jni = $ty;
+
+            fn to_jni_array(values: Vec<Self>) -> Vec<Self::Jni> {
+                values
+            }
+
+            fn from_jni_array(values: Vec<Self::Jni>) -> Vec<Self> {
+                values
+            }
+        })*
+    };
+}
+
+same_array_element!(i8, i16, u16, i32, i64, f32, f64);
+
+impl ArrayElement for bool {
+    type Jni = jboolean;
+
+    fn to_jni_array(values: Vec<Self>) -> Vec<Self::Jni> {
+        values.into_iter().map(jboolean::from).collect()
+    }
+
+    fn from_jni_array(values: Vec<Self::Jni>) -> Vec<Self> {
+        values.into_iter().map(|value| value != 0).collect()
+    }
+}
+
+/// Bytes are signed in java, a `u8` is passed as the `byte` with the same bits
+impl ArrayElement for u8 {
+    type Jni = jbyte;
+
+    fn to_jni_array(values: Vec<Self>) -> Vec<Self::Jni> {
+        values.into_iter().map(|value| value as jbyte).collect()
+    }
+
+    fn from_jni_array(values: Vec<Self::Jni>) -> Vec<Self> {
+        values.into_iter().map(|value| value as u8).collect()
+    }
+}
+
+fn array_length(len: usize) -> Result<jsize> {
+    jsize::try_from(len).map_err(|_| {
+        Error::exception(
+            "java/lang/IllegalArgumentException",
+            format!("{len} elements don't fit in a java array"),
+        )
+    })
+}
+
+macro_rules! primitive_array_j_type {
+    ($($ty:ty => $jni:ty, $kotlin:literal, $java:literal, $descriptor:literal, $new:ident, $get:ident, $set:ident);*) => {
+        $(impl JType for Vec<$ty> {
+            type JType<'a> = JPrimitiveArray<'a, $jni>;
+
+            fn to_j_type<'env>(self, env: &mut JNIEnv<'env>) -> Result<Self::JType<'env>> {
+                let values = <$ty as ArrayElement>::to_jni_array(self);
+                let array = env.$new(array_length(values.len())?)?;
+                env.$set(&array, 0, &values)?;
+                Ok(array)
+            }
         }
-        Ok(object_array)
+
+        impl PrimitiveJType for Vec<$ty> {
+            fn from_j_type<'env>(env: &mut JNIEnv<'env>, j_type: Self::JType<'env>) -> Result<Self> {
+                let len = env.get_array_length(&j_type)?;
+                let mut values = vec![<$jni>::default(); len as usize];
+                env.$get(&j_type, 0, &mut values)?;
+                Ok(<$ty as ArrayElement>::from_jni_array(values))
+            }
+        })*
+    };
+}
+
+java_primitive_arrays!(primitive_array_j_type);
+
+/// A rust type whose slices can borrow the elements of a java primitive array in place, for
+/// `&[T]` and `&mut [T]` arguments
+///
+/// # Safety
+/// `Self` must have the size and alignment of `Jni`, and every value of `Jni` must be a valid
+/// `Self`. That rules out `bool`, which has to be passed as a `Vec<bool>`
+#[diagnostic::on_unimplemented(
+    message = "`&[{Self}]` can't borrow a java array, pass a `Vec<{Self}>` instead"
+)]
+pub unsafe trait SliceElement: Sized {
+    type Jni: TypeArray;
+}
+
+macro_rules! slice_element {
+    ($($ty:ty => $jni:ty),*) => {
+        $(unsafe impl SliceElement for $ty {
+            type Jni = $jni;
+        })*
+    };
+}
+
+slice_element!(i8 => jbyte, u8 => jbyte, i16 => jshort, u16 => jchar, i32 => jint, i64 => jlong, f32 => jfloat, f64 => jdouble);
+
+/// The elements of a java primitive array borrowed by a `&[T]` or `&mut [T]` argument, without
+/// copying them if the JVM can pin the array. Writes through a mutable borrow reach the java
+/// array once this is dropped
+pub struct CriticalArray<'array, T: SliceElement> {
+    env: *mut jni::sys::JNIEnv,
+    array: jni::sys::jarray,
+    elements: NonNull<T>,
+    len: usize,
+    mode: jint,
+    lifetime: PhantomData<&'array T>,
+}
+
+impl<'array, T: SliceElement> CriticalArray<'array, T> {
+    /// The length of `array`. Taken before borrowing any array, as no JNI call can be made while
+    /// one is borrowed, `GetArrayLength` included
+    pub fn length(env: &mut JNIEnv, array: &JPrimitiveArray<T::Jni>) -> Result<usize> {
+        if array.is_null() {
+            return Err(Error::exception(
+                "java/lang/NullPointerException",
+                "The array argument is null",
+            ));
+        }
+        Ok(env.get_array_length(array)? as usize)
+    }
+
+    /// # Safety
+    /// `len` must be the [`length`](Self::length) of `array`. No other JNI function may be
+    /// called and the thread must not wait on another java thread until this is dropped, as the
+    /// JVM may hold off garbage collection meanwhile
+    pub unsafe fn new<'local>(
+        env: &JNIEnv,
+        array: &'array JPrimitiveArray<'local, T::Jni>,
+        len: usize,
+        mutable: bool,
+    ) -> Result<Self> {
+        let env = env.get_raw();
+        // only a mutable borrow can have changed the elements
+        let mode = if mutable { 0 } else { jni::sys::JNI_ABORT };
+        let elements = if len == 0 {
+            NonNull::dangling()
+        } else {
+            let get = unsafe { (**env).GetPrimitiveArrayCritical }.ok_or(
+                jni::errors::Error::JNIEnvMethodNotFound("GetPrimitiveArrayCritical"),
+            )?;
+            let elements = unsafe { get(env, array.as_raw(), std::ptr::null_mut()) };
+            NonNull::new(elements.cast())
+                .ok_or(jni::errors::Error::NullPtr("GetPrimitiveArrayCritical"))?
+        };
+        Ok(CriticalArray {
+            env,
+            array: array.as_raw(),
+            elements,
+            len,
+            mode,
+            lifetime: PhantomData,
+        })
+    }
+
+    pub fn as_slice(&self) -> &[T] {
+        unsafe { std::slice::from_raw_parts(self.elements.as_ptr(), self.len) }
+    }
+
+    pub fn as_mut_slice(&mut self) -> &mut [T] {
+        unsafe { std::slice::from_raw_parts_mut(self.elements.as_ptr(), self.len) }
     }
 }
 
-impl PrimitiveJType for Vec<u8> {
-    fn from_j_type<'env>(env: &mut JNIEnv<'env>, j_type: Self::JType<'env>) -> Result<Self> {
-        Ok(env.convert_byte_array(j_type)?.to_vec())
+/// Fails with an `IllegalArgumentException` if `a` and `b` are the same java array, which two
+/// slice arguments can't both borrow when one of them is mutable
+pub fn distinct_arrays(env: &mut JNIEnv, a: &JObject, b: &JObject) -> Result<()> {
+    if env.is_same_object(a, b)? {
+        return Err(Error::exception(
+            "java/lang/IllegalArgumentException",
+            "The same array can't be passed more than once when one of its uses is mutable",
+        ));
+    }
+    Ok(())
+}
+
+impl<T: SliceElement> Drop for CriticalArray<'_, T> {
+    fn drop(&mut self) {
+        if self.len == 0 {
+            return;
+        }
+        unsafe {
+            if let Some(release) = (**self.env).ReleasePrimitiveArrayCritical {
+                release(
+                    self.env,
+                    self.array,
+                    self.elements.as_ptr().cast(),
+                    self.mode,
+                );
+            }
+        }
+    }
+}
+
+/// The java class a [`DirectBuffer`] maps to
+pub const DIRECT_BUFFER_CLASS: &str = "java.nio.ByteBuffer";
+
+/// The bytes of a direct `java.nio.ByteBuffer`, passed without copying them.
+///
+/// As an argument it borrows the whole capacity of the buffer for the call, ignoring its
+/// position and limit. Returned from a method taking `&self` or `&mut self`, java gets a
+/// buffer over memory of the object, which the generated class keeps reachable for as long as
+/// the buffer is. Closing the object frees it only once its buffers are unreachable, and it
+/// can't be taken by value until then
+pub struct DirectBuffer<'a> {
+    bytes: NonNull<u8>,
+    len: usize,
+    writable: bool,
+    lifetime: PhantomData<&'a mut [u8]>,
+}
+
+impl<'a> DirectBuffer<'a> {
+    /// A buffer java can only read
+    ///
+    /// # Safety
+    /// `bytes` must stay alive and in place until the object returning them is dropped, so
+    /// neither be freed nor reallocated by methods taking `&mut self`
+    pub unsafe fn new(bytes: &'a [u8]) -> Self {
+        DirectBuffer {
+            bytes: NonNull::from(bytes).cast(),
+            len: bytes.len(),
+            writable: false,
+            lifetime: PhantomData,
+        }
+    }
+
+    /// A buffer java can write to, at any time as long as the object lives
+    ///
+    /// # Safety
+    /// Same as [`DirectBuffer::new`], and rust can't assume `bytes` stay the same in between
+    /// reading them
+    pub unsafe fn new_mut(bytes: &'a mut [u8]) -> Self {
+        DirectBuffer {
+            len: bytes.len(),
+            bytes: NonNull::from(bytes).cast(),
+            writable: true,
+            lifetime: PhantomData,
+        }
+    }
+
+    /// `false` for a read only java buffer
+    pub fn is_writable(&self) -> bool {
+        self.writable
+    }
+
+    pub fn as_slice(&self) -> &[u8] {
+        unsafe { std::slice::from_raw_parts(self.bytes.as_ptr(), self.len) }
+    }
+
+    /// The bytes to write to, `None` for a read only java buffer
+    pub fn as_mut_slice(&mut self) -> Option<&mut [u8]> {
+        self.writable
+            .then(|| unsafe { std::slice::from_raw_parts_mut(self.bytes.as_ptr(), self.len) })
+    }
+
+    /// The buffer java gets over `direct`, a read only view of it unless it's writable
+    fn view<'env>(
+        &self,
+        env: &mut JNIEnv<'env>,
+        direct: JByteBuffer<'env>,
+    ) -> Result<JByteBuffer<'env>> {
+        if self.writable {
+            return Ok(direct);
+        }
+        let read_only = env
+            .call_method(&direct, "asReadOnlyBuffer", "()Ljava/nio/ByteBuffer;", &[])?
+            .l()?;
+        Ok(JByteBuffer::from(read_only))
+    }
+
+    /// The bytes of a direct `ByteBuffer` argument, borrowed for the native call `env` belongs to
+    pub fn from_j_type(env: &mut JNIEnv<'a>, j_type: JByteBuffer<'a>) -> Result<Self> {
+        if j_type.is_null() {
+            return Err(Error::exception(
+                "java/lang/NullPointerException",
+                "The ByteBuffer argument is null",
+            ));
+        }
+        if !env.call_method(&j_type, "isDirect", "()Z", &[])?.z()? {
+            return Err(Error::exception(
+                "java/lang/IllegalArgumentException",
+                "The ByteBuffer isn't direct, allocate it with ByteBuffer.allocateDirect",
+            ));
+        }
+        let writable = !env.call_method(&j_type, "isReadOnly", "()Z", &[])?.z()?;
+        let len = env.get_direct_buffer_capacity(&j_type)?;
+        let bytes = match len {
+            0 => NonNull::dangling(),
+            _ => NonNull::new(env.get_direct_buffer_address(&j_type)?)
+                .ok_or(jni::errors::Error::NullPtr("GetDirectBufferAddress"))?,
+        };
+        Ok(DirectBuffer {
+            bytes,
+            len,
+            writable,
+            lifetime: PhantomData,
+        })
+    }
+
+    /// Same as [`DirectBuffer::from_j_type`], with `None` for `null`
+    pub fn from_nullable(env: &mut JNIEnv<'a>, j_type: JByteBuffer<'a>) -> Result<Option<Self>> {
+        if j_type.is_null() {
+            return Ok(None);
+        }
+        Ok(Some(Self::from_j_type(env, j_type)?))
+    }
+}
+
+/// Creates the `ByteBuffer` returned over memory of `obj` and counts it, which keeps `obj` from
+/// being freed until the buffer is unreachable. Called while `obj` is still borrowed
+pub fn buffer_returned<'local>(
+    env: &mut JNIEnv<'local>,
+    obj: &JObject,
+    buffer: DirectBuffer<'_>,
+) -> Result<JByteBuffer<'local>> {
+    let direct = unsafe { env.new_direct_byte_buffer(buffer.bytes.as_ptr(), buffer.len)? };
+    // a read only view, and every slice of it, references the buffer JNI created rather than the
+    // view, so the count has to follow that one
+    env.call_method(
+        obj,
+        "bufferReturned",
+        "(Ljava/nio/ByteBuffer;)V",
+        &[JValue::Object(&direct)],
+    )
+    .context("Failed to count a returned ByteBuffer".to_string())?;
+    buffer.view(env, direct)
+}
+
+/// The number of reachable `ByteBuffer`s returned over memory of `obj`
+fn buffer_count(env: &mut JNIEnv, obj: &JObject) -> Result<i32> {
+    env.get_field(obj, "buffers", "I")
+        .and_then(|buffers| buffers.i())
+        .context("Failed to count the returned ByteBuffers".to_string())
+}
+
+impl std::ops::Deref for DirectBuffer<'_> {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        self.as_slice()
+    }
+}
+
+impl JType for DirectBuffer<'_> {
+    type JType<'a> = JByteBuffer<'a>;
+
+    fn to_j_type<'env>(self, env: &mut JNIEnv<'env>) -> Result<Self::JType<'env>> {
+        let direct = unsafe { env.new_direct_byte_buffer(self.bytes.as_ptr(), self.len)? };
+        self.view(env, direct)
     }
 }
 
@@ -281,3 +1129,475 @@ where
         Ok(object_array)
     }
 }
+
+/// A type that can be passed between rust and the JVM as `Option<Self>`, with `None`
+/// represented by java's `null`. Primitives are boxed, e.g. `Option<i64>` is a `java.lang.Long`
+pub trait JNullable: Sized {
+    type Nullable<'a>;
+    fn to_nullable<'env>(
+        value: Option<Self>,
+        env: &mut JNIEnv<'env>,
+    ) -> Result<Self::Nullable<'env>>;
+}
+
+pub trait PrimitiveJNullable: JNullable {
+    fn from_nullable<'env>(
+        env: &mut JNIEnv<'env>,
+        j_type: Self::Nullable<'env>,
+    ) -> Result<Option<Self>>;
+}
+
+impl<T> JType for Option<T>
+where
+    T: JNullable,
+{
+    type JType<'a> = T::Nullable<'a>;
+
+    fn to_j_type<'env>(self, env: &mut JNIEnv<'env>) -> Result<Self::JType<'env>> {
+        T::to_nullable(self, env)
+    }
+}
+
+impl<T> PrimitiveJType for Option<T>
+where
+    T: PrimitiveJNullable,
+{
+    fn from_j_type<'env>(env: &mut JNIEnv<'env>, j_type: Self::JType<'env>) -> Result<Self> {
+        T::from_nullable(env, j_type)
+    }
+}
+
+macro_rules! nullable_object {
+    ($($ty:ty),*) => {
+        $(impl JNullable for $ty {
+            type Nullable<'a> = <$ty as JType>::JType<'a>;
+
+            fn to_nullable<'env>(
+                value: Option<Self>,
+                env: &mut JNIEnv<'env>,
+            ) -> Result<Self::Nullable<'env>> {
+                match value {
+                    Some(value) => value.to_j_type(env),
+                    None => Ok(JNull::null()),
+                }
+            }
+        }
+
+        impl PrimitiveJNullable for $ty {
+            fn from_nullable<'env>(
+                env: &mut JNIEnv<'env>,
+                j_type: Self::Nullable<'env>,
+            ) -> Result<Option<Self>> {
+                if j_type.is_null() {
+                    return Ok(None);
+                }
+                Ok(Some(Self::from_j_type(env, j_type)?))
+            }
+        })*
+    };
+}
+
+nullable_object!(String, Vec<String>);
+
+impl JNullable for DirectBuffer<'_> {
+    type Nullable<'a> = JByteBuffer<'a>;
+
+    fn to_nullable<'env>(
+        value: Option<Self>,
+        env: &mut JNIEnv<'env>,
+    ) -> Result<Self::Nullable<'env>> {
+        match value {
+            Some(value) => value.to_j_type(env),
+            None => Ok(JNull::null()),
+        }
+    }
+}
+
+macro_rules! nullable_primitive_array {
+    ($($ty:ty => $jni:ty, $kotlin:literal, $java:literal, $descriptor:literal, $new:ident, $get:ident, $set:ident);*) => {
+        nullable_object!($(Vec<$ty>),*);
+    };
+}
+
+java_primitive_arrays!(nullable_primitive_array);
+
+macro_rules! nullable_primitive {
+    ($($ty:ty => $jni:ty, $kotlin:literal, $java:literal, $descriptor:literal, $boxed:literal, $unbox:literal);*) => {
+        $(impl JNullable for $ty {
+            type Nullable<'a> = JObject<'a>;
+
+            fn to_nullable<'env>(
+                value: Option<Self>,
+                env: &mut JNIEnv<'env>,
+            ) -> Result<Self::Nullable<'env>> {
+                let Some(value) = value else {
+                    return Ok(JObject::null());
+                };
+                let j_value = JValue::from(value.into_jni()?);
+                env.call_static_method(
+                    $boxed,
+                    "valueOf",
+                    concat!("(", $descriptor, ")L", $boxed, ";"),
+                    &[j_value],
+                )
+                .and_then(|boxed| boxed.l())
+                .context(format!("Failed to box {} into {}", stringify!($ty), $boxed))
+            }
+        }
+
+        impl PrimitiveJNullable for $ty {
+            fn from_nullable<'env>(
+                env: &mut JNIEnv<'env>,
+                j_type: Self::Nullable<'env>,
+            ) -> Result<Option<Self>> {
+                if j_type.is_null() {
+                    return Ok(None);
+                }
+                let value: $jni = env
+                    .call_method(&j_type, $unbox, concat!("()", $descriptor), &[])
+                    .and_then(|unboxed| unboxed.try_into())
+                    .context(format!("Failed to unbox {} from {}", stringify!($ty), $boxed))?;
+                Ok(Some(Self::from_jni(value)?))
+            }
+        })*
+    };
+}
+
+java_primitives!(nullable_primitive);
+
+impl<T> JNullable for T
+where
+    T: JavaClass,
+{
+    type Nullable<'a> = JObject<'a>;
+
+    fn to_nullable<'env>(
+        value: Option<Self>,
+        env: &mut JNIEnv<'env>,
+    ) -> Result<Self::Nullable<'env>> {
+        match value {
+            Some(value) => value.to_j_type(env),
+            None => Ok(JObject::null()),
+        }
+    }
+}
+
+impl<T> PrimitiveJNullable for T
+where
+    T: JavaClass,
+{
+    fn from_nullable<'env>(
+        env: &mut JNIEnv<'env>,
+        j_type: Self::Nullable<'env>,
+    ) -> Result<Option<Self>> {
+        if j_type.is_null() {
+            return Ok(None);
+        }
+        Ok(Some(T::take_from_object(env, &j_type)?))
+    }
+}
+
+impl<T> JNullable for Vec<T>
+where
+    T: JavaClass + Default,
+{
+    type Nullable<'a> = JObjectArray<'a>;
+
+    fn to_nullable<'env>(
+        value: Option<Self>,
+        env: &mut JNIEnv<'env>,
+    ) -> Result<Self::Nullable<'env>> {
+        match value {
+            Some(value) => value.to_j_type(env),
+            None => Ok(JNull::null()),
+        }
+    }
+}
+
+/// A rust type that can be a field of a `#[java_enum]` variant, stored in a field of the
+/// variant's java class
+#[diagnostic::on_unimplemented(
+    message = "`{Self}` can't be a field of a #[java_enum] variant",
+    note = "fields can be primitives, `String`, `Vec`s of those, other #[java_enum]s or `Option`s of these"
+)]
+pub trait JField: PrimitiveJType {
+    /// JNI descriptor of the field, e.g. `I`
+    const DESCRIPTOR: &'static str;
+    /// JNI descriptor of an `Option<Self>` field, the boxed class for primitives
+    const NULLABLE_DESCRIPTOR: &'static str = Self::DESCRIPTOR;
+
+    fn into_value(j_type: Self::JType<'_>) -> JValueOwned<'_>;
+    fn from_value(value: JValueOwned<'_>) -> Result<Self::JType<'_>>;
+}
+
+macro_rules! primitive_field {
+    ($($ty:ty => $jni:ty, $kotlin:literal, $java:literal, $descriptor:literal, $boxed:literal, $unbox:literal);*) => {
+        $(impl JField for $ty {
+            const DESCRIPTOR: &'static str = $descriptor;
+            const NULLABLE_DESCRIPTOR: &'static str = concat!("L", $boxed, ";");
+
+            fn into_value(j_type: Self::JType<'_>) -> JValueOwned<'_> {
+                j_type.into()
+            }
+
+            fn from_value(value: JValueOwned<'_>) -> Result<Self::JType<'_>> {
+                Ok(value.try_into()?)
+            }
+        })*
+    };
+}
+
+java_primitives!(primitive_field);
+
+macro_rules! object_field {
+    ($($ty:ty => $descriptor:literal),*) => {
+        $(impl JField for $ty {
+            const DESCRIPTOR: &'static str = $descriptor;
+
+            fn into_value(j_type: Self::JType<'_>) -> JValueOwned<'_> {
+                JValueOwned::Object(j_type.into())
+            }
+
+            fn from_value(value: JValueOwned<'_>) -> Result<Self::JType<'_>> {
+                Ok(value.l()?.into())
+            }
+        })*
+    };
+}
+
+object_field!(String => "Ljava/lang/String;", Vec<String> => "[Ljava/lang/String;");
+
+macro_rules! primitive_array_field {
+    ($($ty:ty => $jni:ty, $kotlin:literal, $java:literal, $descriptor:literal, $new:ident, $get:ident, $set:ident);*) => {
+        object_field!($(Vec<$ty> => $descriptor),*);
+    };
+}
+
+java_primitive_arrays!(primitive_array_field);
+
+impl<T> JField for Option<T>
+where
+    T: JField + PrimitiveJNullable,
+    for<'a> T::Nullable<'a>: From<JObject<'a>> + Into<JObject<'a>>,
+{
+    const DESCRIPTOR: &'static str = T::NULLABLE_DESCRIPTOR;
+
+    fn into_value(j_type: Self::JType<'_>) -> JValueOwned<'_> {
+        JValueOwned::Object(j_type.into())
+    }
+
+    fn from_value(value: JValueOwned<'_>) -> Result<Self::JType<'_>> {
+        Ok(value.l()?.into())
+    }
+}
+
+/// A rust enum annotated with `#[java_enum]`. A fieldless enum is passed as the constant of a
+/// java enum, any other as an instance of the subclass of its variant, nested in a sealed
+/// class. The macro implements this along with `JType`, `JNullable` and `JField`
+pub trait JavaEnum: Sized {
+    const LOC: &'static str;
+    const PATH: &'static str;
+
+    /// The constant `name` of the java enum
+    fn constant<'local>(env: &mut JNIEnv<'local>, name: &str) -> Result<JObject<'local>> {
+        let descriptor = format!("L{};", Self::PATH);
+        env.get_static_field(Self::PATH, name, &descriptor)
+            .and_then(|constant| constant.l())
+            .context(format!(
+                "Failed to get the constant {name} of {}",
+                Self::LOC
+            ))
+    }
+
+    /// The ordinal of a constant of the java enum, one of the `len` variants of the rust enum
+    fn ordinal(env: &mut JNIEnv<'_>, obj: &JObject<'_>, len: usize) -> Result<usize> {
+        if obj.is_null() {
+            return Err(Error::exception(
+                "java/lang/NullPointerException",
+                format!("{} is null", Self::LOC),
+            ));
+        }
+        let ordinal = env
+            .call_method(obj, "ordinal", "()I", &[])
+            .and_then(|ordinal| ordinal.i())
+            .context(format!("Failed to get the ordinal of {}", Self::LOC))?;
+        usize::try_from(ordinal)
+            .ok()
+            .filter(|ordinal| *ordinal < len)
+            .ok_or_else(|| {
+                Error::exception(
+                    "java/lang/IllegalArgumentException",
+                    format!("{} has no variant {ordinal}", Self::LOC),
+                )
+            })
+    }
+
+    /// An instance of the subclass `variant` of the sealed class, holding `fields`. A variant
+    /// without fields is a `data object`, which has a single instance
+    fn new_variant<'local>(
+        env: &mut JNIEnv<'local>,
+        variant: &str,
+        fields: &[(&str, JValueOwned<'local>)],
+    ) -> Result<JObject<'local>> {
+        let class = format!("{}${variant}", Self::PATH);
+        if fields.is_empty() {
+            let descriptor = format!("L{class};");
+            return env
+                .get_static_field(&class, "INSTANCE", &descriptor)
+                .and_then(|instance| instance.l())
+                .context(format!("Failed to get the instance of {class}"));
+        }
+        let descriptor: String = once("(")
+            .chain(fields.iter().map(|(descriptor, _)| *descriptor))
+            .chain(once(")V"))
+            .collect();
+        let args: Vec<_> = fields.iter().map(|(_, value)| value.borrow()).collect();
+        env.new_object(&class, &descriptor, &args)
+            .context(format!("Failed to instantiate {class}"))
+    }
+
+    /// The index of the variant whose subclass `obj` is an instance of
+    fn variant_of(env: &mut JNIEnv<'_>, obj: &JObject<'_>, variants: &[&str]) -> Result<usize> {
+        if obj.is_null() {
+            return Err(Error::exception(
+                "java/lang/NullPointerException",
+                format!("{} is null", Self::LOC),
+            ));
+        }
+        for (index, variant) in variants.iter().enumerate() {
+            let class = format!("{}${variant}", Self::PATH);
+            if env.is_instance_of(obj, class.as_str())? {
+                return Ok(index);
+            }
+        }
+        Err(Error::exception(
+            "java/lang/IllegalArgumentException",
+            format!("{} isn't one of the variants of the rust enum", Self::LOC),
+        ))
+    }
+
+    /// Reads the field `name` of an instance of a variant's subclass
+    fn field<T: JField>(env: &mut JNIEnv<'_>, obj: &JObject<'_>, name: &str) -> Result<T> {
+        let value = env
+            .get_field(obj, name, T::DESCRIPTOR)
+            .context(format!("Failed to get the field {name} of {}", Self::LOC))?;
+        let j_type = T::from_value(value)?;
+        T::from_j_type(env, j_type)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        fmt::Debug,
+        sync::{
+            atomic::{AtomicBool, Ordering},
+            RwLock,
+        },
+    };
+
+    use jni::sys::jlong;
+
+    use crate::{
+        manifest::jni_descriptor, ArrayElement, JPrimitive, JavaClass, PRIMITIVES, PRIMITIVE_ARRAYS,
+    };
+
+    static DROPPED: AtomicBool = AtomicBool::new(false);
+
+    struct Dropped;
+
+    impl Drop for Dropped {
+        fn drop(&mut self) {
+            DROPPED.store(true, Ordering::SeqCst);
+        }
+    }
+
+    unsafe impl JavaClass for Dropped {
+        const LOC: &'static str = "beep.Dropped";
+        const PATH: &'static str = "beep/Dropped";
+        type Storage = RwLock<Self>;
+    }
+
+    fn round_trip<T: JPrimitive + Copy + PartialEq + Debug>(values: &[T]) {
+        for value in values {
+            let jni = value.into_jni().expect("fits");
+            assert_eq!(T::from_jni(jni).expect("valid"), *value);
+        }
+    }
+
+    fn array_round_trip<T: ArrayElement + Clone + PartialEq + Debug>(values: &[T]) {
+        let jni = T::to_jni_array(values.to_vec());
+        assert_eq!(jni.len(), values.len());
+        assert_eq!(T::from_jni_array(jni), values);
+    }
+
+    #[test]
+    fn test_primitive_round_trip() {
+        round_trip(&[true, false]);
+        round_trip(&[i8::MIN, 0, i8::MAX]);
+        round_trip(&[i16::MIN, 0, i16::MAX]);
+        round_trip(&[u16::MIN, 0xd800, u16::MAX]);
+        round_trip(&['a', '\0', 'é', '\u{ffff}']);
+        round_trip(&[i32::MIN, 0, i32::MAX]);
+        round_trip(&[i64::MIN, 0, i64::MAX]);
+        round_trip(&[f32::MIN, -0.0, f32::INFINITY, f32::MAX]);
+        round_trip(&[f64::MIN, -0.0, f64::NEG_INFINITY, f64::MAX]);
+
+        assert!(bool::from_jni(2).expect("any non zero"));
+        assert!('🦀'.into_jni().is_err());
+        assert!(char::from_jni(0xd83e).is_err());
+    }
+
+    #[test]
+    fn test_primitive_array_round_trip() {
+        array_round_trip(&[true, false, true]);
+        array_round_trip(&[i8::MIN, 0, i8::MAX]);
+        array_round_trip(&[u8::MIN, 0x80, u8::MAX]);
+        array_round_trip(&[i16::MIN, 0, i16::MAX]);
+        array_round_trip(&[u16::MIN, 0xdc00, u16::MAX]);
+        array_round_trip(&[i32::MIN, 0, i32::MAX]);
+        array_round_trip(&[i64::MIN, 0, i64::MAX]);
+        array_round_trip(&[f32::MIN, 0.5, f32::MAX]);
+        array_round_trip(&[f64::MIN, 0.5, f64::MAX]);
+        array_round_trip::<i32>(&[]);
+
+        assert_eq!(u8::to_jni_array(vec![0x80, 0xff]), [i8::MIN, -1]);
+        assert_eq!(bool::to_jni_array(vec![true, false]), [1, 0]);
+    }
+
+    #[test]
+    fn test_drop_while_borrowed() {
+        let handle = Box::into_raw(Box::new(RwLock::new(Dropped))) as jlong;
+        Dropped::use_shared(handle, |_| {
+            assert!(!unsafe { Dropped::drop_by_handle(handle) });
+            Ok(())
+        })
+        .expect("still there");
+        assert!(!DROPPED.load(Ordering::SeqCst));
+        assert!(unsafe { Dropped::drop_by_handle(handle) });
+        assert!(DROPPED.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    fn test_primitive_tables() {
+        for primitive in PRIMITIVES {
+            assert_eq!(
+                jni_descriptor(primitive.kotlin, false),
+                primitive.descriptor
+            );
+            assert_eq!(
+                jni_descriptor(primitive.kotlin, true),
+                format!("L{};", primitive.boxed)
+            );
+        }
+        for array in PRIMITIVE_ARRAYS {
+            assert_eq!(jni_descriptor(array.kotlin, false), array.descriptor);
+            let element = PRIMITIVES
+                .iter()
+                .find(|primitive| format!("{}[]", primitive.java) == array.java)
+                .expect("array of a primitive");
+            assert_eq!(array.descriptor, format!("[{}", element.descriptor));
+            assert_eq!(array.kotlin, format!("{}Array", element.kotlin));
+        }
+    }
+}
@@ -0,0 +1,223 @@
+use serde::{Deserialize, Serialize};
+
+use crate::{PRIMITIVES, PRIMITIVE_ARRAYS};
+
+/// The API of a crate's `#[java_class]` impl blocks and `#[java_enum]`s. The macro and the cli
+/// both derive the names of the generated classes and native methods from it, and
+/// `cli generate` writes it as json next to the kotlin sources for other tools to build on
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Manifest {
+    /// name of the native library the classes load
+    pub library: String,
+    pub classes: Vec<Class>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub enums: Vec<Enum>,
+}
+
+/// A `#[java_class]` impl block
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Class {
+    /// full rust path of the type, e.g. `crate::models::Bpe`
+    pub rust_path: String,
+    /// package of the java class, e.g. `dev.gigapixel.tokenizers`
+    pub package: String,
+    /// simple name of the java class, the last segment of the rust path
+    pub name: String,
+    pub sync: SyncStrategy,
+    pub methods: Vec<Method>,
+}
+
+impl Class {
+    /// The name of the class in kotlin, e.g. `dev.gigapixel.tokenizers.Bpe`
+    pub fn qualified_name(&self) -> String {
+        format!("{}.{}", self.package, self.name)
+    }
+
+    /// The binary name of the class the JVM uses, e.g. `dev/gigapixel/tokenizers/Bpe`
+    pub fn jni_name(&self) -> String {
+        self.qualified_name().replace('.', "/")
+    }
+}
+
+/// A `#[java_enum]` enum. Without fields it's a kotlin `enum class`, otherwise a `sealed class`
+/// with a subclass for every variant
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Enum {
+    /// full rust path of the enum, e.g. `crate::utils::TruncationStrategy`
+    pub rust_path: String,
+    /// package of the java class, e.g. `dev.gigapixel.tokenizers`
+    pub package: String,
+    /// simple name of the java class, the name of the rust enum
+    pub name: String,
+    pub variants: Vec<Variant>,
+}
+
+impl Enum {
+    /// The name of the class in kotlin, e.g. `dev.gigapixel.tokenizers.TruncationStrategy`
+    pub fn qualified_name(&self) -> String {
+        format!("{}.{}", self.package, self.name)
+    }
+
+    /// The binary name of the class the JVM uses, e.g. `dev/gigapixel/tokenizers/TruncationStrategy`
+    pub fn jni_name(&self) -> String {
+        self.qualified_name().replace('.', "/")
+    }
+
+    /// Whether no variant has fields, which makes the enum a kotlin `enum class`
+    pub fn is_fieldless(&self) -> bool {
+        self.variants
+            .iter()
+            .all(|variant| variant.fields.is_empty())
+    }
+}
+
+/// A variant of a `#[java_enum]`
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Variant {
+    /// name of the rust variant, e.g. `LongestFirst`
+    pub rust_name: String,
+    /// name of the constant of an `enum class`, e.g. `LONGEST_FIRST`, or of the subclass of a
+    /// `sealed class`, e.g. `LongestFirst`
+    pub name: String,
+    /// whether the fields are unnamed, as in `Fixed(usize)`. Their rust names are then `0`, `1`…
+    pub tuple: bool,
+    pub fields: Vec<Param>,
+}
+
+/// How the value behind a java object is synchronized, the `sync` argument of `#[java_class]`
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum SyncStrategy {
+    #[serde(rename = "rwlock")]
+    RwLock,
+    #[serde(rename = "mutex")]
+    Mutex,
+    #[serde(rename = "none")]
+    None,
+    #[serde(rename = "thread_confined")]
+    ThreadConfined,
+}
+
+/// How a method takes `self` or a `#[java_class]` argument
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum RefType {
+    Shared,
+    Mut,
+    /// taken by value, which moves the value out of the java object
+    Owned,
+}
+
+/// A method of a `#[java_class]` impl block
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Method {
+    /// name of the rust method, e.g. `from_file`
+    pub rust_name: String,
+    /// name of the kotlin method, e.g. `fromFile`
+    pub name: String,
+    /// name of the static native method the macro exports, e.g. `fromFileExtern`
+    pub native_name: String,
+    /// how `self` is taken, `None` for associated functions
+    pub receiver: Option<RefType>,
+    pub params: Vec<Param>,
+    /// the type handed back to the JVM, `None` for `()`
+    pub returns: Option<TypeRef>,
+    /// whether the rust method returns a `Result` whose `Err` is thrown as a `RustException`
+    pub throws: bool,
+    /// JNI descriptor of the native method, e.g. `(Ldev/gigapixel/Bpe;I)J`. Only known once
+    /// the `#[java_class]` types are resolved
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub descriptor: Option<String>,
+}
+
+/// An argument of a method, besides `self`, or a field of an enum variant
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Param {
+    /// name of the rust argument, e.g. `file_name`
+    pub rust_name: String,
+    /// name of the kotlin parameter, e.g. `fileName`
+    pub name: String,
+    #[serde(rename = "type")]
+    pub ty: TypeRef,
+}
+
+/// The type of a parameter or return value
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct TypeRef {
+    /// the rust type with `Self` replaced, e.g. `Option < & Bpe >`
+    pub rust: String,
+    /// whether the type is an `Option`, which is passed as a nullable kotlin type
+    pub nullable: bool,
+    /// `Shared` for `&T` and `Mut` for `&mut T` arguments
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub borrow: Option<RefType>,
+    /// kotlin type without nullability, e.g. `Long` or `dev.gigapixel.tokenizers.Bpe`. Only
+    /// known once the `#[java_class]` types are resolved
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub kotlin: Option<String>,
+    /// whether the type is a `#[java_class]`
+    #[serde(default)]
+    pub object: bool,
+    /// JNI descriptor of the type, e.g. `J` or `Ljava/lang/Long;` when nullable
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub descriptor: Option<String>,
+}
+
+/// A rust type passed as a java primitive, see [`crate::PRIMITIVES`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Primitive {
+    /// e.g. `i32`
+    pub rust: &'static str,
+    /// e.g. `Int`
+    pub kotlin: &'static str,
+    /// e.g. `int`
+    pub java: &'static str,
+    /// e.g. `I`
+    pub descriptor: &'static str,
+    /// binary name of the class a nullable value is boxed in, e.g. `java/lang/Integer`
+    pub boxed: &'static str,
+}
+
+impl Primitive {
+    /// The simple name of the boxed class, e.g. `Integer`
+    pub fn boxed_name(&self) -> &'static str {
+        self.boxed.rsplit('/').next().unwrap_or(self.boxed)
+    }
+}
+
+/// A `Vec` passed as a java primitive array, see [`crate::PRIMITIVE_ARRAYS`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PrimitiveArray {
+    /// rust type of the elements, e.g. `u8`
+    pub element: &'static str,
+    /// e.g. `ByteArray`
+    pub kotlin: &'static str,
+    /// e.g. `byte[]`
+    pub java: &'static str,
+    /// e.g. `[B`
+    pub descriptor: &'static str,
+}
+
+/// The JNI descriptor of a kotlin type, boxing nullable primitives
+pub fn jni_descriptor(kotlin: &str, nullable: bool) -> String {
+    if let Some(primitive) = PRIMITIVES.iter().find(|p| p.kotlin == kotlin) {
+        return if nullable {
+            format!("L{};", primitive.boxed)
+        } else {
+            primitive.descriptor.to_string()
+        };
+    }
+    if let Some(array) = PRIMITIVE_ARRAYS.iter().find(|a| a.kotlin == kotlin) {
+        return array.descriptor.to_string();
+    }
+    match kotlin {
+        "Unit" => "V".to_string(),
+        "String" => "Ljava/lang/String;".to_string(),
+        _ => match kotlin
+            .strip_prefix("Array<")
+            .and_then(|inner| inner.strip_suffix('>'))
+        {
+            Some(inner) => format!("[{}", jni_descriptor(inner, true)),
+            None => format!("L{};", kotlin.replace('.', "/")),
+        },
+    }
+}
@@ -0,0 +1,208 @@
+//! Java's modified UTF-8, the encoding of the JNI string functions. It differs from UTF-8 in
+//! two ways: `\0` takes the two bytes `C0 80`, and a character outside the basic multilingual
+//! plane is encoded as its two UTF-16 surrogates of three bytes each, like CESU-8. Java
+//! strings can also hold unpaired surrogates, which no rust string can.
+
+use std::{borrow::Cow, char::REPLACEMENT_CHARACTER};
+
+/// Decodes the modified UTF-8 of a java string. `bytes` are borrowed when they are valid
+/// UTF-8 as well, as they then mean the same string. Unpaired surrogates and malformed
+/// sequences become U+FFFD, each on their own
+pub fn decode(bytes: &[u8]) -> Cow<'_, str> {
+    match std::str::from_utf8(bytes) {
+        Ok(string) => Cow::Borrowed(string),
+        Err(_) => Cow::Owned(
+            char::decode_utf16(Utf16Units { bytes, low: None })
+                .map(|unit| unit.unwrap_or(REPLACEMENT_CHARACTER))
+                .collect(),
+        ),
+    }
+}
+
+/// Encodes `string` as modified UTF-8, borrowing it when it has neither a `\0` nor a character
+/// outside the basic multilingual plane
+pub fn encode(string: &str) -> Cow<'_, [u8]> {
+    // only `\0` and the lead bytes of four byte sequences are encoded differently
+    if !string.bytes().any(|byte| byte == 0 || byte >= 0xF0) {
+        return Cow::Borrowed(string.as_bytes());
+    }
+    let mut bytes = Vec::with_capacity(string.len() + string.len() / 2);
+    for char in string.chars() {
+        match char {
+            '\0' => bytes.extend([0xC0, 0x80]),
+            '\u{10000}'.. => {
+                for unit in char.encode_utf16(&mut [0; 2]) {
+                    push_unit(&mut bytes, *unit);
+                }
+            }
+            _ => bytes.extend_from_slice(char.encode_utf8(&mut [0; 4]).as_bytes()),
+        }
+    }
+    Cow::Owned(bytes)
+}
+
+/// Appends the three byte encoding of a UTF-16 unit of at least `U+0800`
+fn push_unit(bytes: &mut Vec<u8>, unit: u16) {
+    bytes.extend([
+        0xE0 | (unit >> 12) as u8,
+        0x80 | (unit >> 6) as u8 & 0x3F,
+        0x80 | unit as u8 & 0x3F,
+    ]);
+}
+
+/// The UTF-16 units of modified UTF-8, with U+FFFD for malformed sequences. Four byte UTF-8
+/// sequences, which java doesn't produce, are read as the pair of units of their character
+struct Utf16Units<'a> {
+    bytes: &'a [u8],
+    /// The low surrogate of the last four byte sequence
+    low: Option<u16>,
+}
+
+impl Iterator for Utf16Units<'_> {
+    type Item = u16;
+
+    fn next(&mut self) -> Option<u16> {
+        if let Some(low) = self.low.take() {
+            return Some(low);
+        }
+        let (&lead, rest) = self.bytes.split_first()?;
+        let continuation = |at: usize| {
+            rest.get(at)
+                .filter(|&&byte| byte & 0xC0 == 0x80)
+                .map(|&byte| u32::from(byte & 0x3F))
+        };
+        let (value, len) = match lead {
+            0x00..=0x7F => (Some(u32::from(lead)), 1),
+            0xC0..=0xDF => (
+                continuation(0).map(|c1| u32::from(lead & 0x1F) << 6 | c1),
+                2,
+            ),
+            0xE0..=0xEF => (
+                continuation(0)
+                    .zip(continuation(1))
+                    .map(|(c1, c2)| u32::from(lead & 0x0F) << 12 | c1 << 6 | c2),
+                3,
+            ),
+            0xF0..=0xF7 => (
+                continuation(0)
+                    .zip(continuation(1))
+                    .zip(continuation(2))
+                    .map(|((c1, c2), c3)| u32::from(lead & 0x07) << 18 | c1 << 12 | c2 << 6 | c3),
+                4,
+            ),
+            _ => (None, 1),
+        };
+        let Some(value) = value else {
+            // skip the lead byte only, so a truncated sequence doesn't swallow the next one
+            self.bytes = rest;
+            return Some(REPLACEMENT_CHARACTER as u16);
+        };
+        self.bytes = &self.bytes[len..];
+        match char::from_u32(value).filter(|_| value > 0xFFFF) {
+            Some(char) => {
+                let mut units = [0; 2];
+                char.encode_utf16(&mut units);
+                self.low = Some(units[1]);
+                Some(units[0])
+            }
+            None if value > 0xFFFF => Some(REPLACEMENT_CHARACTER as u16),
+            None => Some(value as u16),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::borrow::Cow;
+
+    use proptest::prelude::*;
+
+    use super::{decode, encode};
+
+    /// What `GetStringUTFChars` returns for a java string of `units`
+    fn java_encode(units: impl IntoIterator<Item = u16>) -> Vec<u8> {
+        let mut bytes = vec![];
+        for unit in units {
+            match unit {
+                0x01..=0x7F => bytes.push(unit as u8),
+                0x00 | 0x80..=0x7FF => {
+                    bytes.extend([0xC0 | (unit >> 6) as u8, 0x80 | unit as u8 & 0x3F])
+                }
+                _ => super::push_unit(&mut bytes, unit),
+            }
+        }
+        bytes
+    }
+
+    /// Strings with plenty of `\0` and characters outside the basic multilingual plane
+    fn string() -> impl Strategy<Value = String> {
+        proptest::collection::vec(
+            prop_oneof![
+                Just('\0'),
+                any::<char>(),
+                proptest::char::range('\u{80}', '\u{FFFF}'),
+                proptest::char::range('\u{10000}', '\u{10FFFF}'),
+            ],
+            0..64,
+        )
+        .prop_map(String::from_iter)
+    }
+
+    /// UTF-16 units, with plenty of unpaired surrogates
+    fn units() -> impl Strategy<Value = Vec<u16>> {
+        proptest::collection::vec(
+            prop_oneof![any::<u16>(), 0xD800u16..0xE000, 0u16..0x80],
+            0..64,
+        )
+    }
+
+    #[test]
+    fn test_examples() {
+        assert_eq!(encode("a😀b").as_ref(), java_encode("a😀b".encode_utf16()));
+        assert_eq!(
+            encode("a😀b").as_ref(),
+            b"a\xED\xA0\xBD\xED\xB8\x80b".as_slice()
+        );
+        assert_eq!(encode("a\0b").as_ref(), b"a\xC0\x80b".as_slice());
+        assert_eq!(decode(b"a\xED\xA0\xBD\xED\xB8\x80b"), "a😀b");
+        assert_eq!(decode(b"a\xC0\x80b"), "a\0b");
+        // a high surrogate without its low one, then a lone low surrogate
+        assert_eq!(decode(b"\xED\xA0\xBDa\xED\xB8\x80"), "\u{FFFD}a\u{FFFD}");
+        assert_eq!(decode(b"\xE2\x82"), "\u{FFFD}\u{FFFD}");
+        assert_eq!(decode("a😀b".as_bytes()), "a😀b");
+        assert!(matches!(decode("héllo".as_bytes()), Cow::Borrowed(_)));
+        assert!(matches!(encode("héllo"), Cow::Borrowed(_)));
+    }
+
+    proptest! {
+        #[test]
+        fn test_encode_like_java(string in string()) {
+            let encoded = encode(&string);
+            prop_assert_eq!(encoded.as_ref(), java_encode(string.encode_utf16()));
+        }
+
+        #[test]
+        fn test_round_trip(string in string()) {
+            let encoded = encode(&string);
+            let decoded = decode(&encoded);
+            prop_assert_eq!(&decoded, &string);
+            let borrowed = !string.chars().any(|char| char == '\0' || char > '\u{FFFF}');
+            prop_assert_eq!(matches!(decoded, Cow::Borrowed(_)), borrowed);
+            prop_assert!(!encoded.contains(&0));
+        }
+
+        #[test]
+        fn test_decode_unpaired_surrogates(units in units()) {
+            let encoded = java_encode(units.iter().copied());
+            prop_assert_eq!(decode(&encoded), String::from_utf16_lossy(&units));
+        }
+
+        #[test]
+        fn test_decode_any_bytes(bytes in proptest::collection::vec(any::<u8>(), 0..64)) {
+            let decoded = decode(&bytes);
+            if let Ok(string) = std::str::from_utf8(&bytes) {
+                prop_assert_eq!(decoded, string);
+            }
+        }
+    }
+}
@@ -0,0 +1,236 @@
+//! How the value behind a java object is shared between the JVM threads calling into it,
+//! picked per class with `#[java_class("pkg", sync = "...")]`
+
+use std::{
+    cell::RefCell,
+    mem::ManuallyDrop,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Mutex, RwLock, TryLockError,
+    },
+    thread::{self, ThreadId},
+};
+
+/// Why the value behind a java object could not be accessed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AccessError {
+    /// an earlier call panicked while holding the lock
+    Poisoned,
+    /// a thread confined value was used from a thread other than the one that created it
+    WrongThread,
+    /// the value is already borrowed in a way that conflicts with this access
+    Borrowed,
+}
+
+/// # Safety
+/// the value handed out by [`Storage::read`] must not be mutated by any other thread while `f`
+/// runs, and `Self` must be safe to access from every thread that can reach the java object
+pub unsafe trait Storage<T>: Sized {
+    fn new(value: T) -> Self;
+
+    fn into_inner(self) -> Result<T, AccessError>;
+
+    fn read<R>(&self, f: impl FnOnce(&T) -> R) -> Result<R, AccessError>;
+
+    /// Runs `f` while nothing else, on any thread, borrows the value, failing with
+    /// [`AccessError::Borrowed`] instead of waiting when something does. Used to zero the handle
+    /// of a java object before its value is taken out and freed
+    fn try_exclusive<R>(&self, f: impl FnOnce() -> R) -> Result<R, AccessError>;
+}
+
+fn try_lock_error<G>(err: TryLockError<G>) -> AccessError {
+    match err {
+        TryLockError::Poisoned(_) => AccessError::Poisoned,
+        TryLockError::WouldBlock => AccessError::Borrowed,
+    }
+}
+
+/// Storage that can also hand out `&mut T`, needed by `&mut self` methods and `&mut T` arguments
+///
+/// # Safety
+/// the value handed out by [`StorageMut::write`] must not be accessed by anything else while `f`
+/// runs
+pub unsafe trait StorageMut<T>: Storage<T> {
+    fn write<R>(&self, f: impl FnOnce(&mut T) -> R) -> Result<R, AccessError>;
+}
+
+/// `sync = "rwlock"`, the default. Any number of readers or a single writer at a time
+unsafe impl<T: Send + Sync> Storage<T> for RwLock<T> {
+    fn new(value: T) -> Self {
+        RwLock::new(value)
+    }
+
+    fn into_inner(self) -> Result<T, AccessError> {
+        RwLock::into_inner(self).map_err(|_| AccessError::Poisoned)
+    }
+
+    fn read<R>(&self, f: impl FnOnce(&T) -> R) -> Result<R, AccessError> {
+        let guard = RwLock::read(self).map_err(|_| AccessError::Poisoned)?;
+        Ok(f(&guard))
+    }
+
+    fn try_exclusive<R>(&self, f: impl FnOnce() -> R) -> Result<R, AccessError> {
+        let _guard = RwLock::try_write(self).map_err(try_lock_error)?;
+        Ok(f())
+    }
+}
+
+unsafe impl<T: Send + Sync> StorageMut<T> for RwLock<T> {
+    fn write<R>(&self, f: impl FnOnce(&mut T) -> R) -> Result<R, AccessError> {
+        let mut guard = RwLock::write(self).map_err(|_| AccessError::Poisoned)?;
+        Ok(f(&mut guard))
+    }
+}
+
+/// `sync = "mutex"`. Every call is exclusive, so the value only has to be `Send`
+unsafe impl<T: Send> Storage<T> for Mutex<T> {
+    fn new(value: T) -> Self {
+        Mutex::new(value)
+    }
+
+    fn into_inner(self) -> Result<T, AccessError> {
+        Mutex::into_inner(self).map_err(|_| AccessError::Poisoned)
+    }
+
+    fn read<R>(&self, f: impl FnOnce(&T) -> R) -> Result<R, AccessError> {
+        let guard = self.lock().map_err(|_| AccessError::Poisoned)?;
+        Ok(f(&guard))
+    }
+
+    fn try_exclusive<R>(&self, f: impl FnOnce() -> R) -> Result<R, AccessError> {
+        let _guard = self.try_lock().map_err(try_lock_error)?;
+        Ok(f())
+    }
+}
+
+unsafe impl<T: Send> StorageMut<T> for Mutex<T> {
+    fn write<R>(&self, f: impl FnOnce(&mut T) -> R) -> Result<R, AccessError> {
+        let mut guard = self.lock().map_err(|_| AccessError::Poisoned)?;
+        Ok(f(&mut guard))
+    }
+}
+
+/// `sync = "none"`. The value is shared between threads as is, without any locking, so it
+/// has to be `Sync` and can't be borrowed mutably. Only the calls in flight are counted, so
+/// that taking the value by value fails while another thread is still using it
+pub struct Unsynchronized<T> {
+    value: T,
+    /// number of calls borrowing the value, [`EXCLUSIVE`] while it is being taken
+    borrows: AtomicUsize,
+}
+
+const EXCLUSIVE: usize = usize::MAX;
+
+/// Ends a borrow of an [`Unsynchronized`] value, also when the call panics
+struct BorrowCount<'a>(&'a AtomicUsize);
+
+impl Drop for BorrowCount<'_> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::Release);
+    }
+}
+
+unsafe impl<T: Send + Sync> Storage<T> for Unsynchronized<T> {
+    fn new(value: T) -> Self {
+        Unsynchronized {
+            value,
+            borrows: AtomicUsize::new(0),
+        }
+    }
+
+    fn into_inner(self) -> Result<T, AccessError> {
+        Ok(self.value)
+    }
+
+    fn read<R>(&self, f: impl FnOnce(&T) -> R) -> Result<R, AccessError> {
+        self.borrows
+            .fetch_update(Ordering::Acquire, Ordering::Relaxed, |borrows| {
+                (borrows < EXCLUSIVE - 1).then_some(borrows + 1)
+            })
+            .map_err(|_| AccessError::Borrowed)?;
+        let _count = BorrowCount(&self.borrows);
+        Ok(f(&self.value))
+    }
+
+    fn try_exclusive<R>(&self, f: impl FnOnce() -> R) -> Result<R, AccessError> {
+        self.borrows
+            .compare_exchange(0, EXCLUSIVE, Ordering::Acquire, Ordering::Relaxed)
+            .map_err(|_| AccessError::Borrowed)?;
+        let result = f();
+        self.borrows.store(0, Ordering::Release);
+        Ok(result)
+    }
+}
+
+/// `sync = "thread_confined"`. The value can only be used from the thread that created it,
+/// so it needs neither `Send` nor `Sync`. As the JVM's `Cleaner` runs on a thread of its own,
+/// objects that are not closed on their owning thread are leaked instead of dropped.
+pub struct ThreadConfined<T> {
+    owner: ThreadId,
+    value: ManuallyDrop<RefCell<T>>,
+}
+
+impl<T> ThreadConfined<T> {
+    fn check_thread(&self) -> Result<(), AccessError> {
+        if thread::current().id() == self.owner {
+            Ok(())
+        } else {
+            Err(AccessError::WrongThread)
+        }
+    }
+}
+
+// The value is only ever touched from `owner`, every access checks the current thread first
+unsafe impl<T> Send for ThreadConfined<T> {}
+unsafe impl<T> Sync for ThreadConfined<T> {}
+
+unsafe impl<T> Storage<T> for ThreadConfined<T> {
+    fn new(value: T) -> Self {
+        ThreadConfined {
+            owner: thread::current().id(),
+            value: ManuallyDrop::new(RefCell::new(value)),
+        }
+    }
+
+    fn into_inner(self) -> Result<T, AccessError> {
+        self.check_thread()?;
+        let mut this = ManuallyDrop::new(self);
+        // `this` is never dropped, so the value is only taken once
+        let value = unsafe { ManuallyDrop::take(&mut this.value) };
+        Ok(value.into_inner())
+    }
+
+    fn read<R>(&self, f: impl FnOnce(&T) -> R) -> Result<R, AccessError> {
+        self.check_thread()?;
+        let value = self.value.try_borrow().map_err(|_| AccessError::Borrowed)?;
+        Ok(f(&value))
+    }
+
+    fn try_exclusive<R>(&self, f: impl FnOnce() -> R) -> Result<R, AccessError> {
+        self.check_thread()?;
+        let _value = self
+            .value
+            .try_borrow_mut()
+            .map_err(|_| AccessError::Borrowed)?;
+        Ok(f())
+    }
+}
+
+unsafe impl<T> StorageMut<T> for ThreadConfined<T> {
+    fn write<R>(&self, f: impl FnOnce(&mut T) -> R) -> Result<R, AccessError> {
+        self.check_thread()?;
+        let mut value = self
+            .value
+            .try_borrow_mut()
+            .map_err(|_| AccessError::Borrowed)?;
+        Ok(f(&mut value))
+    }
+}
+
+impl<T> Drop for ThreadConfined<T> {
+    fn drop(&mut self) {
+        if self.check_thread().is_ok() {
+            unsafe { ManuallyDrop::drop(&mut self.value) }
+        }
+    }
+}
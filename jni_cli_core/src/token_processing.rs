@@ -4,69 +4,417 @@ use std::iter::once;
 use convert_case::{Case, Casing};
 
 use itertools::Itertools;
-use proc_macro2::{self, Ident, Span, TokenStream};
-use quote::{quote, ToTokens};
+use proc_macro2::{self, Group, Ident, Span, TokenStream, TokenTree};
+use quote::{format_ident, quote, ToTokens};
 use syn::{
-    punctuated::Punctuated, spanned::Spanned, token::Comma, Error, ImplItem, ImplItemFn, ItemImpl,
-    PatType, Receiver,
+    parse::{Parse, ParseStream},
+    punctuated::Punctuated,
+    spanned::Spanned,
+    token::Comma,
+    Error, Fields, GenericArgument, ImplItem, ImplItemFn, ItemEnum, ItemImpl, PatType,
+    PathArguments, Receiver, Token, Type, TypePath,
 };
-use syn::{File, LitStr, Meta, MetaList, ReturnType};
+use syn::{File, Item, LitStr, Meta, MetaList, ReturnType, UseName, UseRename, UseTree};
 
-enum RefType {
-    Shared,
-    Mut,
+use crate::manifest::{
+    jni_descriptor, Class, Enum, Method, Param, RefType, SyncStrategy, TypeRef, Variant,
+};
+use crate::{DIRECT_BUFFER_CLASS, PRIMITIVES, PRIMITIVE_ARRAYS};
+
+/// Name of the checked exception class generated in every package with a `#[java_class]`,
+/// thrown when a method returning `Result` produces an `Err`
+pub const RUST_EXCEPTION: &str = "RustException";
+
+/// Name of the unchecked exception class generated next to [`RUST_EXCEPTION`], thrown when
+/// a generated method panics
+pub const RUST_PANIC_EXCEPTION: &str = "RustPanicException";
+
+impl SyncStrategy {
+    fn storage(self) -> TokenStream {
+        match self {
+            SyncStrategy::RwLock => quote! {std::sync::RwLock<Self>},
+            SyncStrategy::Mutex => quote! {std::sync::Mutex<Self>},
+            SyncStrategy::None => quote! {jni_cli_core::storage::Unsynchronized<Self>},
+            SyncStrategy::ThreadConfined => quote! {jni_cli_core::storage::ThreadConfined<Self>},
+        }
+    }
+}
+
+/// Arguments of `#[java_class("package", sync = "...")]`
+struct JavaClassAttr {
+    package: String,
+    sync: SyncStrategy,
+}
+
+impl Parse for JavaClassAttr {
+    fn parse(input: ParseStream) -> Result<Self, syn::Error> {
+        let package: LitStr = input.parse().map_err(|err| {
+            syn::Error::new(
+                err.span(),
+                "The `java_class` attribute must have a string literal supplied to specify the class path",
+            )
+        })?;
+        let mut sync = SyncStrategy::RwLock;
+        while !input.is_empty() {
+            input.parse::<Comma>()?;
+            if input.is_empty() {
+                break;
+            }
+            let key: Ident = input.parse()?;
+            if key != "sync" {
+                return Err(syn::Error::new(
+                    key.span(),
+                    format!("Unknown `java_class` argument `{key}`"),
+                ));
+            }
+            input.parse::<Token![=]>()?;
+            let value: LitStr = input.parse()?;
+            sync = match value.value().as_str() {
+                "rwlock" => SyncStrategy::RwLock,
+                "mutex" => SyncStrategy::Mutex,
+                "none" => SyncStrategy::None,
+                "thread_confined" => SyncStrategy::ThreadConfined,
+                other => {
+                    return Err(syn::Error::new(
+                        value.span(),
+                        format!(
+                            "Unknown sync `{other}`, expected one of \"rwlock\", \"mutex\", \"none\" or \"thread_confined\""
+                        ),
+                    ))
+                }
+            };
+        }
+        Ok(JavaClassAttr {
+            package: package.value(),
+            sync,
+        })
+    }
+}
+
+/// Arguments of `#[java_enum("package")]`
+struct JavaEnumAttr {
+    package: String,
+}
+
+impl Parse for JavaEnumAttr {
+    fn parse(input: ParseStream) -> Result<Self, syn::Error> {
+        let package: LitStr = input.parse().map_err(|err| {
+            syn::Error::new(
+                err.span(),
+                "The `java_enum` attribute must have a string literal supplied to specify the class path",
+            )
+        })?;
+        input.parse::<Option<Comma>>()?;
+        Ok(JavaEnumAttr {
+            package: package.value(),
+        })
+    }
 }
 
 struct ParseFn {
     fn_name: Ident,
     ref_type: Option<RefType>,
     args: Vec<PatType>,
-    output: ReturnType,
-}
-pub type PackageLookup = HashMap<String, String>;
-
-pub fn fill_lookup(rust_code: &str, lookup: &mut PackageLookup) -> Result<(), syn::Error> {
-    let syntax_tree: File = syn::parse_str(rust_code)?;
-    let mut visitor = ImplVisitor { impls: Vec::new() };
-    visitor.visit_file(&syntax_tree);
-    let java_classes = visitor.impls.iter().flat_map(|impl_item| {
-        impl_item.attrs.iter().filter_map(|attr| match &attr.meta {
-            Meta::List(MetaList { path, tokens, .. })
-                if path
-                    .get_ident()
-                    .map(|ident| ident.to_string().as_str() == "java_class")
-                    .unwrap_or(false) =>
-            {
-                Some(
-                    syn::parse2::<LitStr>(tokens.clone())
-                        .map(|res| (impl_item.self_ty.to_token_stream(), res.value().to_string())),
-                )
-            }
-            _ => None,
-        })
-    });
-    for res in java_classes {
-        let (self_ty, path) = res?;
-        let self_ty = self_ty.to_string();
+    /// the type handed back to the JVM, `None` for `()`
+    output: Option<Type>,
+    /// whether the rust method returns a `Result` whose `Err` is thrown as a `RustException`
+    throws: bool,
+}
+/// The kotlin classes, e.g. `beep.boop.Foo`, of the `#[java_class]` and `#[java_enum]` types by
+/// their full rust path, e.g. `crate::boop::Foo`
+#[derive(Default, Debug)]
+pub struct PackageLookup {
+    pub classes: HashMap<String, String>,
+    pub enums: HashMap<String, String>,
+}
 
-        if lookup
-            .insert(self_ty.clone(), format!("{path}.{self_ty}"))
-            .is_some()
-        {
+impl PackageLookup {
+    fn insert(&mut self, rust_path: String, class: String, is_enum: bool) -> Result<(), Error> {
+        let all = || self.classes.iter().chain(&self.enums);
+        if all().any(|(other, _)| *other == rust_path) {
+            return Err(syn::Error::new(
+                Span::call_site(),
+                format!("Found more than one #[java_class] or #[java_enum] for {rust_path}"),
+            ));
+        }
+        if let Some((other, _)) = all().find(|(_, other)| **other == class) {
             return Err(syn::Error::new(
                 Span::call_site(),
-                format!("Found more than one #[java_class] for struct_name {self_ty}",),
+                format!("Both {other} and {rust_path} are the java class {class}"),
             ));
         }
+        if is_enum {
+            self.enums.insert(rust_path, class);
+        } else {
+            self.classes.insert(rust_path, class);
+        }
+        Ok(())
+    }
+}
+
+/// The items of one rust module, without the items of its submodules
+pub struct RustModule {
+    /// path of the module, e.g. `crate::models::bpe`
+    pub path: String,
+    pub items: Vec<Item>,
+}
+
+impl RustModule {
+    pub fn parse(path: impl Into<String>, rust_code: &str) -> Result<RustModule, syn::Error> {
+        let File { items, .. } = syn::parse_str(rust_code)?;
+        Ok(RustModule {
+            path: path.into(),
+            items,
+        })
+    }
+
+    /// The impl blocks of the module annotated with `#[java_class]`, with their parsed attribute
+    fn java_class_impls(&self) -> Result<Vec<(&ItemImpl, JavaClassAttr)>, syn::Error> {
+        let impls = self.items.iter().filter_map(|item| match item {
+            Item::Impl(impl_item) => Some(impl_item),
+            _ => None,
+        });
+        impls
+            .flat_map(|impl_item| {
+                impl_item
+                    .attrs
+                    .iter()
+                    .filter_map(move |attr| match &attr.meta {
+                        Meta::List(MetaList { path, tokens, .. })
+                            if path.is_ident("java_class") =>
+                        {
+                            Some(
+                                syn::parse2::<JavaClassAttr>(tokens.clone())
+                                    .map(|a| (impl_item, a)),
+                            )
+                        }
+                        _ => None,
+                    })
+            })
+            .collect()
+    }
+
+    /// The enums of the module annotated with `#[java_enum]`, with their parsed attribute
+    fn java_enums(&self) -> Result<Vec<(&ItemEnum, JavaEnumAttr)>, syn::Error> {
+        let enums = self.items.iter().filter_map(|item| match item {
+            Item::Enum(enum_item) => Some(enum_item),
+            _ => None,
+        });
+        enums
+            .flat_map(|enum_item| {
+                enum_item
+                    .attrs
+                    .iter()
+                    .filter_map(move |attr| match &attr.meta {
+                        Meta::List(MetaList { path, tokens, .. }) if path.is_ident("java_enum") => {
+                            Some(
+                                syn::parse2::<JavaEnumAttr>(tokens.clone()).map(|a| (enum_item, a)),
+                            )
+                        }
+                        _ => None,
+                    })
+            })
+            .collect()
+    }
+}
+
+pub fn fill_lookup(module: &RustModule, lookup: &mut PackageLookup) -> Result<(), syn::Error> {
+    let types = TypeResolver::new(module, lookup);
+    let mut classes = vec![];
+    for (impl_item, attr) in module.java_class_impls()? {
+        let rust_path = types.rust_path(&impl_item.self_ty)?;
+        let name = type_name(&impl_item.self_ty)?;
+        classes.push((rust_path, format!("{}.{name}", attr.package), false));
+    }
+    for (enum_item, attr) in module.java_enums()? {
+        let rust_path = types.enum_path(enum_item);
+        let name = &enum_item.ident;
+        classes.push((rust_path, format!("{}.{name}", attr.package), true));
+    }
+    for (rust_path, class, is_enum) in classes {
+        lookup.insert(rust_path, class, is_enum)?;
     }
     Ok(())
 }
 
-fn parse_fn(_path: &str, _ty: &TokenStream, input: &ImplItemFn) -> Result<ParseFn, syn::Error> {
+/// Resolves the types written in a module to the `#[java_class]` or `#[java_enum]` they name,
+/// following the module's `use` declarations
+struct TypeResolver<'a> {
+    module: &'a str,
+    /// the names brought into scope by `use`, e.g. `Foo` for `crate::a::Foo`
+    imports: HashMap<String, Vec<String>>,
+    /// the modules whose items are brought into scope by `use a::*`
+    globs: Vec<Vec<String>>,
+    lookup: &'a PackageLookup,
+}
+
+impl<'a> TypeResolver<'a> {
+    fn new(module: &'a RustModule, lookup: &'a PackageLookup) -> TypeResolver<'a> {
+        let mut resolver = TypeResolver {
+            module: &module.path,
+            imports: HashMap::new(),
+            globs: vec![],
+            lookup,
+        };
+        for item in &module.items {
+            if let Item::Use(item_use) = item {
+                resolver.add_use(vec![], &item_use.tree);
+            }
+        }
+        resolver
+    }
+
+    fn add_use(&mut self, mut prefix: Vec<String>, tree: &UseTree) {
+        match tree {
+            UseTree::Path(path) => {
+                prefix.push(path.ident.to_string());
+                self.add_use(prefix, &path.tree);
+            }
+            UseTree::Name(UseName { ident }) if ident == "self" => {
+                if let Some(name) = prefix.last() {
+                    self.imports.insert(name.clone(), prefix);
+                }
+            }
+            UseTree::Name(UseName { ident }) => {
+                prefix.push(ident.to_string());
+                self.imports.insert(ident.to_string(), prefix);
+            }
+            UseTree::Rename(UseRename { ident, rename, .. }) => {
+                if ident != "self" {
+                    prefix.push(ident.to_string());
+                }
+                self.imports.insert(rename.to_string(), prefix);
+            }
+            UseTree::Glob(_) => self.globs.push(prefix),
+            UseTree::Group(group) => {
+                for tree in &group.items {
+                    self.add_use(prefix.clone(), tree);
+                }
+            }
+        }
+    }
+
+    /// The full paths a type path written in the module may refer to, the most likely first
+    fn full_paths(&self, segments: &[String]) -> Vec<String> {
+        let Some((first, rest)) = segments.split_first() else {
+            return vec![];
+        };
+        if let Some(path) = self.absolute_path(segments) {
+            return vec![path];
+        }
+        let mut paths = vec![];
+        if let Some(import) = self.imports.get(first) {
+            let import = import.iter().chain(rest).cloned().collect::<Vec<_>>();
+            paths.push(
+                self.absolute_path(&import)
+                    .unwrap_or_else(|| format!("{}::{}", self.module, import.join("::"))),
+            );
+        }
+        paths.push(format!("{}::{}", self.module, segments.join("::")));
+        for glob in &self.globs {
+            let glob = glob.iter().chain(segments).cloned().collect::<Vec<_>>();
+            paths.extend(self.absolute_path(&glob));
+        }
+        paths
+    }
+
+    /// Resolves paths starting with `crate`, `self` or `super`
+    fn absolute_path(&self, segments: &[String]) -> Option<String> {
+        let (first, mut rest) = segments.split_first()?;
+        let mut module: Vec<&str> = match first.as_str() {
+            "crate" => vec!["crate"],
+            "self" => self.module.split("::").collect(),
+            "super" => {
+                let mut module: Vec<_> = self.module.split("::").collect();
+                module.pop();
+                while let Some(("super", supers)) = rest.split_first().map(|(s, r)| (s.as_str(), r))
+                {
+                    module.pop();
+                    rest = supers;
+                }
+                module
+            }
+            _ => return None,
+        };
+        module.extend(rest.iter().map(String::as_str));
+        Some(module.join("::"))
+    }
+
+    /// The full path of the type of an impl block in the module
+    fn rust_path(&self, self_ty: &Type) -> Result<String, syn::Error> {
+        let segments = path_segments(self_ty)
+            .ok_or_else(|| syn::Error::new(self_ty.span(), "#[java_class] needs a struct type"))?;
+        Ok(self.full_paths(&segments).remove(0))
+    }
+
+    /// The full path of a `#[java_enum]` of the module
+    fn enum_path(&self, enum_item: &ItemEnum) -> String {
+        format!("{}::{}", self.module, enum_item.ident)
+    }
+
+    /// The kotlin class of the `#[java_class]` named by the token string of a type
+    fn class(&self, ty: &str) -> Option<&'a String> {
+        self.find(ty, &self.lookup.classes)
+    }
+
+    /// The kotlin class of the `#[java_enum]` named by the token string of a type
+    fn enum_class(&self, ty: &str) -> Option<&'a String> {
+        self.find(ty, &self.lookup.enums)
+    }
+
+    fn find(&self, ty: &str, classes: &'a HashMap<String, String>) -> Option<&'a String> {
+        let segments = path_segments(&syn::parse_str(ty).ok()?)?;
+        self.full_paths(&segments)
+            .iter()
+            .find_map(|path| classes.get(path))
+    }
+}
+
+/// The identifiers of the path of a type, leaving out generic arguments
+fn path_segments(ty: &Type) -> Option<Vec<String>> {
+    let Type::Path(TypePath { qself: None, path }) = ty else {
+        return None;
+    };
+    Some(
+        path.segments
+            .iter()
+            .map(|segment| segment.ident.to_string())
+            .collect(),
+    )
+}
+
+/// The name of the java class of the impl block's type, the last segment of its path
+fn type_name(ty: &Type) -> Result<Ident, syn::Error> {
+    match ty {
+        Type::Path(TypePath { qself: None, path }) => path
+            .segments
+            .last()
+            .map(|segment| segment.ident.clone())
+            .ok_or_else(|| syn::Error::new(ty.span(), "#[java_class] needs a struct type")),
+        _ => Err(syn::Error::new(
+            ty.span(),
+            "#[java_class] needs a struct type",
+        )),
+    }
+}
+
+fn parse_fn(ty: &TokenStream, input: &ImplItemFn) -> Result<ParseFn, syn::Error> {
     let input = input.clone();
     let fn_name = input.sig.ident;
     let args = input.sig.inputs;
-    let output = input.sig.output;
+    let output = match input.sig.output {
+        ReturnType::Type(arrow, output) => {
+            ReturnType::Type(arrow, Box::new(replace_self_type(&output, ty)?))
+        }
+        output => output,
+    };
+    let (output, throws) = match output {
+        ReturnType::Default => (None, false),
+        ReturnType::Type(_, ty) => match result_ok_type(&ty) {
+            Some(ok_ty) => (Some(ok_ty.clone()).filter(|ty| !is_unit(ty)), true),
+            None => (Some(*ty).filter(|ty| !is_unit(ty)), false),
+        },
+    };
     let mut self_ty: Option<RefType> = None;
     let mut rest_args: Vec<PatType> = Vec::with_capacity(args.len());
     for arg in args.iter() {
@@ -77,18 +425,17 @@ fn parse_fn(_path: &str, _ty: &TokenStream, input: &ImplItemFn) -> Result<ParseF
                 mutability,
                 ..
             }) => {
-                // comment
-                reference.as_ref().ok_or_else(|| {
-                    syn::Error::new(Span::call_site(), "Must only call self as reference Boop")
-                })?;
-                let self_call = match mutability {
-                    Some(_) => RefType::Mut,
-                    None => RefType::Shared,
+                let self_call = match (reference, mutability) {
+                    (None, _) => RefType::Owned,
+                    (Some(_), Some(_)) => RefType::Mut,
+                    (Some(_), None) => RefType::Shared,
                 };
                 self_ty = Some(self_call)
             }
             syn::FnArg::Typed(pat @ PatType { .. }) => {
-                rest_args.push(pat.clone());
+                let mut pat = pat.clone();
+                pat.ty = Box::new(replace_self_type(&pat.ty, ty)?);
+                rest_args.push(pat);
             }
         }
     }
@@ -97,164 +444,541 @@ fn parse_fn(_path: &str, _ty: &TokenStream, input: &ImplItemFn) -> Result<ParseF
         ref_type: self_ty,
         args: rest_args,
         output,
+        throws,
     })
 }
 
-fn get_kotlin_output<'a>(
-    output: &'a str,
-    self_ty_str: &'a str,
-    lookup: &'a PackageLookup,
-) -> Result<(&'a str, bool), syn::Error> {
-    let res = if output == "Self" {
-        (
-            lookup
-                .get(self_ty_str)
-                .ok_or_else(|| {
-                    syn::Error::new(
-                        Span::call_site(),
-                        format!(
-                            "Failed to find {self_ty_str} in java_class lookup. This is a bug."
-                        ),
-                    )
-                })?
-                .as_str(),
-            true,
-        )
-    } else if let Some(package) = lookup.get(output) {
-        (package.as_str(), true)
-    } else {
-        (map_kotlin_type_from_rust(output)?, false)
+/// Replaces `Self` in `ty` with the type of the impl block, as the generated extern fns live
+/// outside of it
+fn replace_self_type(ty: &Type, self_ty: &TokenStream) -> Result<Type, syn::Error> {
+    fn replace(tokens: TokenStream, self_ty: &TokenStream) -> TokenStream {
+        tokens
+            .into_iter()
+            .flat_map(|tree| -> TokenStream {
+                match tree {
+                    TokenTree::Ident(ident) if ident == "Self" => self_ty.clone(),
+                    TokenTree::Group(group) => {
+                        let mut replaced =
+                            Group::new(group.delimiter(), replace(group.stream(), self_ty));
+                        replaced.set_span(group.span());
+                        TokenTree::Group(replaced).into()
+                    }
+                    tree => tree.into(),
+                }
+            })
+            .collect()
+    }
+    syn::parse2(replace(ty.to_token_stream(), self_ty))
+}
+
+/// Replaces the lifetimes in `ty` with `'static`, so the JNI type of a return value borrowing
+/// from `self` can be named outside of the method
+fn static_lifetimes(ty: &Type) -> Result<Type, syn::Error> {
+    fn replace(tokens: TokenStream) -> TokenStream {
+        let mut after_quote = false;
+        tokens
+            .into_iter()
+            .map(|tree| {
+                let is_quote = matches!(&tree, TokenTree::Punct(punct) if punct.as_char() == '\'');
+                let tree = match tree {
+                    TokenTree::Ident(ident) if after_quote => {
+                        TokenTree::Ident(Ident::new("static", ident.span()))
+                    }
+                    TokenTree::Group(group) => {
+                        let mut replaced = Group::new(group.delimiter(), replace(group.stream()));
+                        replaced.set_span(group.span());
+                        TokenTree::Group(replaced)
+                    }
+                    tree => tree,
+                };
+                after_quote = is_quote;
+                tree
+            })
+            .collect()
+    }
+    syn::parse2(replace(ty.to_token_stream()))
+}
+
+/// Returns the kind of borrow and the borrowed `#[java_class]` type for `&T` and `&mut T`
+fn ref_arg_type(ty: &Type) -> Option<(RefType, &Type)> {
+    let Type::Reference(reference) = ty else {
+        return None;
     };
-    Ok(res)
+    if matches!(*reference.elem, Type::Slice(_)) || is_str(&reference.elem) {
+        return None;
+    }
+    let ref_type = match reference.mutability {
+        Some(_) => RefType::Mut,
+        None => RefType::Shared,
+    };
+    Some((ref_type, &reference.elem))
 }
 
-fn kotlin_class_method(
-    path: &str,
-    self_ty: &TokenStream,
-    input: &ImplItemFn,
-    lookup: &PackageLookup,
-) -> Result<Option<String>, syn::Error> {
-    let ParseFn {
-        fn_name,
-        ref_type,
-        args,
-        output,
-    } = parse_fn(path, self_ty, input)?;
-    let Some(_ref_type) = ref_type else {
-        return Ok(None);
+/// Returns the kind of borrow and the element type for `&[T]` and `&mut [T]`, which borrow the
+/// elements of a java primitive array
+fn slice_arg_type(ty: &Type) -> Option<(RefType, &Type)> {
+    let Type::Reference(reference) = ty else {
+        return None;
     };
-    let self_ty_str = self_ty.to_string();
-    let kotlin_args_iter = args.iter().map(|PatType { pat, .. }| {
-        let var = pat.to_token_stream().to_string().to_case(Case::Camel);
-        format!("{var}")
-    });
+    let Type::Slice(slice) = &*reference.elem else {
+        return None;
+    };
+    let ref_type = match reference.mutability {
+        Some(_) => RefType::Mut,
+        None => RefType::Shared,
+    };
+    Some((ref_type, &slice.elem))
+}
+
+fn is_str(ty: &Type) -> bool {
+    matches!(ty, Type::Path(TypePath { qself: None, path }) if path.is_ident("str"))
+}
+
+/// Whether `ty` is `&str` or `Cow<str>`, which borrow the characters of a java string
+fn is_str_arg(ty: &Type) -> bool {
+    let path = match ty {
+        Type::Reference(reference) => return is_str(&reference.elem),
+        Type::Path(TypePath { qself: None, path }) => path,
+        _ => return false,
+    };
+    let Some(segment) = path
+        .segments
+        .last()
+        .filter(|segment| segment.ident == "Cow")
+    else {
+        return false;
+    };
+    let PathArguments::AngleBracketed(args) = &segment.arguments else {
+        return false;
+    };
+    args.args
+        .iter()
+        .any(|arg| matches!(arg, GenericArgument::Type(ty) if is_str(ty)))
+}
+
+/// Returns `T` if `ty` is a `Result<T, E>`, or any other alias named `Result` such as
+/// `io::Result<T>`
+fn result_ok_type(ty: &Type) -> Option<&Type> {
+    generic_type(ty, "Result")
+}
+
+/// The `T` of an `Option<T>`, also when written as `std::option::Option<T>`
+fn option_type(ty: &Type) -> Option<&Type> {
+    generic_type(ty, "Option")
+}
+
+/// The first type argument of a path type whose last segment is `name`
+fn generic_type<'a>(ty: &'a Type, name: &str) -> Option<&'a Type> {
+    let Type::Path(TypePath { qself: None, path }) = ty else {
+        return None;
+    };
+    let segment = path.segments.last()?;
+    if segment.ident != name {
+        return None;
+    }
+    let PathArguments::AngleBracketed(args) = &segment.arguments else {
+        return None;
+    };
+    args.args.iter().find_map(|arg| match arg {
+        GenericArgument::Type(ty) => Some(ty),
+        _ => None,
+    })
+}
+
+fn is_unit(ty: &Type) -> bool {
+    matches!(ty, Type::Tuple(tuple) if tuple.elems.is_empty())
+}
+
+/// The token string of the `T` of an `Option<T>`, from the token string of a type
+fn option_inner(ty: &str) -> Option<String> {
+    let ty: Type = syn::parse_str(ty).ok()?;
+    option_type(&ty).map(|inner| inner.to_token_stream().to_string())
+}
+
+/// Whether the token string `ty` is a `DirectBuffer`, with or without its lifetime and path
+fn is_direct_buffer(ty: &str) -> bool {
+    let ty = ty.split(" <").next().unwrap_or(ty);
+    ty.rsplit(":: ").next() == Some("DirectBuffer")
+}
+
+/// Whether a method of the class returns a `DirectBuffer` over memory of the object, which the
+/// generated class then has to count
+pub(crate) fn returns_buffers(class: &Class) -> bool {
+    class.methods.iter().any(|method| {
+        method.returns.as_ref().is_some_and(|ty| {
+            let inner = option_inner(&ty.rust);
+            is_direct_buffer(inner.as_deref().unwrap_or(&ty.rust))
+        })
+    })
+}
+
+/// Whether the token string `ty` is a `Cow<str>`, with or without its lifetime and path
+fn is_cow_str(ty: &str) -> bool {
+    let ty = ty.rsplit(":: ").next().unwrap_or(ty);
+    ty.starts_with("Cow <") && ty.ends_with(" str >")
+}
+
+/// Returns `T` for the token string of `&T` or `&mut T`
+fn ref_inner(ty: &str) -> Option<&str> {
+    let ty = ty.strip_prefix("& ")?;
+    Some(ty.strip_prefix("mut ").unwrap_or(ty))
+}
+
+/// The manifest of a type, not yet resolved
+fn type_ref(ty: &Type) -> TypeRef {
+    let rust = ty.to_token_stream().to_string();
+    TypeRef {
+        nullable: option_type(ty).is_some(),
+        borrow: ref_arg_type(ty)
+            .or_else(|| slice_arg_type(ty))
+            .map(|(ref_type, _)| ref_type),
+        rust,
+        kotlin: None,
+        object: false,
+        descriptor: None,
+    }
+}
+
+/// The manifest of a method, with its types not yet resolved
+fn method_manifest(parsed: &ParseFn) -> Method {
+    let name = parsed.fn_name.to_string().to_case(Case::Camel);
+    Method {
+        rust_name: parsed.fn_name.to_string(),
+        native_name: format!("{name}Extern"),
+        name,
+        receiver: parsed.ref_type,
+        params: parsed
+            .args
+            .iter()
+            .map(|PatType { pat, ty, .. }| {
+                let rust_name = pat.to_token_stream().to_string();
+                Param {
+                    name: rust_name.to_case(Case::Camel),
+                    rust_name,
+                    ty: type_ref(ty),
+                }
+            })
+            .collect(),
+        returns: parsed.output.as_ref().map(type_ref),
+        throws: parsed.throws,
+        descriptor: None,
+    }
+}
+
+fn impl_fns(impl_item: &ItemImpl) -> impl Iterator<Item = &ImplItemFn> {
+    impl_item
+        .items
+        .iter()
+        .filter_map(|impl_item| match impl_item {
+            ImplItem::Fn(fn_item) => Some(fn_item),
+            _ => None,
+        })
+}
 
-    let mut kotlin_args_with_types_iter = args.iter().map(|PatType { pat, ty, .. }| {
-        let var = pat.to_token_stream().to_string().to_case(Case::Camel);
-        let ty = ty.to_token_stream().to_string();
-        let kotlin_type = if ty == self_ty_str {
-            &self_ty_str
+/// The manifest of a `#[java_class]` impl block, with the types of its methods not yet resolved
+fn class_manifest(
+    rust_path: String,
+    attr: JavaClassAttr,
+    impl_item: &ItemImpl,
+) -> Result<Class, syn::Error> {
+    let self_ty = impl_item.self_ty.to_token_stream();
+    let methods = impl_fns(impl_item)
+        .map(|fn_item| {
+            let parsed = parse_fn(&self_ty, fn_item)?;
+            let returns_buffer = parsed.output.as_ref().is_some_and(|output| {
+                let output = option_type(output).unwrap_or(output);
+                is_direct_buffer(&output.to_token_stream().to_string())
+            });
+            if returns_buffer && !matches!(parsed.ref_type, Some(RefType::Shared | RefType::Mut)) {
+                return Err(syn::Error::new(
+                    fn_item.sig.span(),
+                    "A returned DirectBuffer points into memory of the object, so the method needs `&self` or `&mut self`",
+                ));
+            }
+            Ok(method_manifest(&parsed))
+        })
+        .collect::<Result<_, syn::Error>>()?;
+    Ok(Class {
+        rust_path,
+        package: attr.package,
+        name: type_name(&impl_item.self_ty)?.to_string(),
+        sync: attr.sync,
+        methods,
+    })
+}
+
+/// Fills in the kotlin types and JNI descriptors of the methods of `class`, whose impl block
+/// is for the type `self_ty`
+fn resolve_types(class: &mut Class, self_ty: &str, types: &TypeResolver) -> Result<(), Error> {
+    let self_class = class.qualified_name();
+    let resolve = |ty: &mut TypeRef| -> Result<(), Error> {
+        let inner = option_inner(&ty.rust);
+        let inner = inner.as_deref().unwrap_or(&ty.rust);
+        let inner = ref_inner(inner).unwrap_or(inner);
+        let (kotlin, object) = if let Some(class) = types.class(inner) {
+            (class.clone(), true)
+        } else if let Some(class) = types.enum_class(inner) {
+            (class.clone(), false)
+        } else if inner == self_ty {
+            (self_class.clone(), true)
         } else {
-            map_kotlin_type_from_rust(&ty).expect("Failed to create type")
+            (map_kotlin_type_from_rust(inner)?.to_string(), false)
         };
-        format!("{var}: {kotlin_type}")
-    });
-    let j_args = once("handle".to_string())
-        .chain(kotlin_args_iter)
-        .join(", ");
+        ty.descriptor = Some(jni_descriptor(&kotlin, ty.nullable));
+        ty.kotlin = Some(kotlin);
+        ty.object = object;
+        Ok(())
+    };
+    let self_descriptor = jni_descriptor(&self_class, false);
+    for method in &mut class.methods {
+        let mut descriptor = "(".to_string();
+        if method.receiver.is_some() {
+            descriptor.push_str(&self_descriptor);
+        }
+        for param in &mut method.params {
+            resolve(&mut param.ty)?;
+            descriptor.extend(param.ty.descriptor.clone());
+        }
+        descriptor.push(')');
+        match &mut method.returns {
+            Some(returns) => {
+                resolve(returns)?;
+                descriptor.extend(returns.descriptor.clone());
+            }
+            None => descriptor.push('V'),
+        }
+        method.descriptor = Some(descriptor);
+    }
+    Ok(())
+}
+
+/// The manifest of a `#[java_enum]`, with the types of its fields not yet resolved
+fn enum_manifest(
+    rust_path: String,
+    attr: JavaEnumAttr,
+    enum_item: &ItemEnum,
+) -> Result<Enum, syn::Error> {
+    if !enum_item.generics.params.is_empty() {
+        return Err(syn::Error::new(
+            enum_item.generics.span(),
+            "A #[java_enum] can't be generic",
+        ));
+    }
+    let fieldless = enum_item
+        .variants
+        .iter()
+        .all(|variant| variant.fields.is_empty());
+    let variants = enum_item
+        .variants
+        .iter()
+        .map(|variant| {
+            let rust_name = variant.ident.to_string();
+            let unnamed = variant.fields.len() == 1;
+            let fields = variant
+                .fields
+                .iter()
+                .enumerate()
+                .map(|(index, field)| {
+                    let (rust_name, name) = match &field.ident {
+                        Some(ident) => (ident.to_string(), ident.to_string().to_case(Case::Camel)),
+                        None if unnamed => (index.to_string(), "value".to_string()),
+                        None => (index.to_string(), format!("value{index}")),
+                    };
+                    Param {
+                        rust_name,
+                        name,
+                        ty: type_ref(&field.ty),
+                    }
+                })
+                .collect();
+            Variant {
+                name: if fieldless {
+                    rust_name.to_case(Case::UpperSnake)
+                } else {
+                    rust_name.clone()
+                },
+                rust_name,
+                tuple: matches!(variant.fields, Fields::Unnamed(_)),
+                fields,
+            }
+        })
+        .collect();
+    Ok(Enum {
+        rust_path,
+        package: attr.package,
+        name: enum_item.ident.to_string(),
+        variants,
+    })
+}
+
+/// Fills in the kotlin types and JNI descriptors of the fields of `enum_item`. They can't be
+/// `#[java_class]` objects, which nothing would free once the JVM holds them in a field
+fn resolve_enum_types(enum_item: &mut Enum, types: &TypeResolver) -> Result<(), Error> {
+    for variant in &mut enum_item.variants {
+        for field in &mut variant.fields {
+            let ty = &mut field.ty;
+            let inner = option_inner(&ty.rust);
+            let inner = inner.as_deref().unwrap_or(&ty.rust);
+            if types.class(inner).is_some() {
+                return Err(Error::new(
+                    Span::call_site(),
+                    format!(
+                        "The field {} of {}::{} is a #[java_class], which can't be a field of a #[java_enum]",
+                        field.rust_name, enum_item.name, variant.rust_name
+                    ),
+                ));
+            }
+            let kotlin = match types.enum_class(inner) {
+                Some(class) => class.clone(),
+                None => map_kotlin_type_from_rust(inner)?.to_string(),
+            };
+            ty.descriptor = Some(jni_descriptor(&kotlin, ty.nullable));
+            ty.kotlin = Some(kotlin);
+        }
+    }
+    Ok(())
+}
+
+/// The kotlin type of a resolved type, e.g. `Long?`
+fn kotlin_type(ty: &TypeRef) -> Result<String, syn::Error> {
+    let kotlin = ty.kotlin.as_ref().ok_or_else(|| {
+        syn::Error::new(
+            Span::call_site(),
+            format!(
+                "The kotlin type of {} isn't resolved. This is a bug.",
+                ty.rust
+            ),
+        )
+    })?;
+    Ok(if ty.nullable {
+        format!("{kotlin}?")
+    } else {
+        kotlin.clone()
+    })
+}
+
+/// The kotlin return type of the method and whether it is a `#[java_class]` that needs a cleaner
+fn kotlin_output(method: &Method) -> Result<(String, bool), syn::Error> {
+    match &method.returns {
+        Some(ty) => Ok((kotlin_type(ty)?, ty.object)),
+        None => Ok(("Unit".to_string(), false)),
+    }
+}
 
-    let j_args_with_types = kotlin_args_with_types_iter.join(", ");
+fn kotlin_args_with_types(method: &Method) -> Result<Vec<String>, syn::Error> {
+    method
+        .params
+        .iter()
+        .map(|Param { name, ty, .. }| Ok(format!("{name}: {}", kotlin_type(ty)?)))
+        .collect()
+}
 
-    let output = match output {
-        ReturnType::Default => String::new(),
-        ReturnType::Type(_, ty) => ty.to_token_stream().to_string(),
+/// Statements releasing the `#[java_class]` objects passed by value, which rust takes
+/// ownership of
+fn kotlin_release_consumed(method: &Method) -> String {
+    method
+        .params
+        .iter()
+        .filter(|Param { ty, .. }| ty.object && ty.borrow.is_none())
+        .map(|Param { name, ty, .. }| {
+            let safe_call = if ty.nullable { "?." } else { "." };
+            format!("{name}{safe_call}releaseIfConsumed()")
+        })
+        .join("\n            ")
+}
+
+/// Wraps the call to the native fn so that consumed objects are released even if it throws
+fn kotlin_call(call: &str, release_consumed: &str) -> String {
+    if release_consumed.is_empty() {
+        call.to_string()
+    } else {
+        format!(
+            r#"try {{
+            {call}
+        }} finally {{
+            {release_consumed}
+        }}"#
+        )
+    }
+}
+
+fn kotlin_class_method(method: &Method) -> Result<Option<String>, syn::Error> {
+    let Some(ref_type) = method.receiver else {
+        return Ok(None);
     };
+    let kotlin_args_iter = method.params.iter().map(|param| param.name.clone());
+    let j_args = once("this".to_string()).chain(kotlin_args_iter).join(", ");
 
-    let (output_class, cleanup) = get_kotlin_output(&output, &self_ty_str, lookup)?;
-    let j_fn_name = fn_name.to_string().to_case(Case::Camel);
+    let j_args_with_types = kotlin_args_with_types(method)?.join(", ");
+
+    let (output_class, cleanup) = kotlin_output(method)?;
+    let release_consumed = kotlin_release_consumed(method);
+    let release_consumed = match ref_type {
+        RefType::Owned if release_consumed.is_empty() => "releaseIfConsumed()".to_string(),
+        RefType::Owned => format!("releaseIfConsumed()\n            {release_consumed}"),
+        _ => release_consumed,
+    };
+    let call = kotlin_call(
+        &format!("Companion.{}({j_args})", method.native_name),
+        &release_consumed,
+    );
     Ok(Some(kotlin_class_fn(
-        &self_ty_str,
-        &j_fn_name,
+        &method.name,
         &j_args_with_types,
-        &j_args,
-        &output,
-        output_class,
+        &call,
+        &output_class,
         cleanup,
+        method.throws,
     )))
 }
 
-fn kotlin_companion_method(
-    path: &str,
-    self_ty: &TokenStream,
-    input: &ImplItemFn,
-    lookup: &PackageLookup,
-) -> Result<Option<String>, syn::Error> {
-    let ParseFn {
-        fn_name,
-        ref_type,
-        args,
-        output,
-    } = parse_fn(path, self_ty, input)?;
-    let self_ty_str = self_ty.to_string();
-    let kotlin_args_iter = args.iter().map(|PatType { pat, ty, .. }| {
-        let var = pat.to_token_stream().to_string().to_case(Case::Camel);
-        let ty = ty.to_token_stream().to_string();
-        let kotlin_type = if ty == self_ty_str {
-            &self_ty_str
-        } else {
-            map_kotlin_type_from_rust(&ty).expect("Failed to create type")
-        };
-        format!("{var}: {kotlin_type}")
-    });
-    let j_args_with_types = ref_type
-        .as_ref()
-        .map(|_| format!("handle: Long"))
+fn kotlin_companion_method(class: &Class, method: &Method) -> Result<String, syn::Error> {
+    let kotlin_args_iter = kotlin_args_with_types(method)?;
+    let j_args_with_types = method
+        .receiver
+        .map(|_| format!("self: {}", class.name))
         .into_iter()
         .chain(kotlin_args_iter)
         .join(", ");
 
-    let output = match output {
-        ReturnType::Default => String::new(),
-        ReturnType::Type(_, ty) => ty.to_token_stream().to_string(),
-    };
-
-    let (output_package, cleanup) = get_kotlin_output(&output, &self_ty_str, lookup)?;
-    let j_fn_name = fn_name.to_string().to_case(Case::Camel);
-    let public_fn = if ref_type.as_ref().is_none() {
-        let j_args = args
-            .iter()
-            .map(|PatType { pat, .. }| {
-                let var = pat.to_token_stream().to_string().to_case(Case::Camel);
-                format!("{var}")
-            })
-            .join(", ");
+    let (output_package, cleanup) = kotlin_output(method)?;
+    let public_fn = if method.receiver.is_none() {
+        let j_args = method.params.iter().map(|param| &param.name).join(", ");
+        let call = kotlin_call(
+            &format!("{}({j_args})", method.native_name),
+            &kotlin_release_consumed(method),
+        );
 
         kotlin_static_fn(
-            &self_ty_str,
-            &j_fn_name,
+            &method.name,
             &j_args_with_types,
-            &j_args,
-            &output,
-            output_package,
+            &call,
+            &output_package,
             cleanup,
+            method.throws,
         )
     } else {
         String::new()
     };
 
-    let private_static_native_fn =
-        kotlin_private_native_fn(&j_fn_name, &j_args_with_types, output_package);
-    Ok(Some(format!(
+    let private_static_native_fn = kotlin_private_native_fn(
+        &method.native_name,
+        &j_args_with_types,
+        &output_package,
+        method.throws,
+    );
+    Ok(format!(
         r#"
         {public_fn}
         {private_static_native_fn}
 
                 "#
-    )))
+    ))
 }
 
 fn java_method_fn(
-    path: &str,
+    class: &Class,
+    method: &Method,
     ty: &TokenStream,
     input: &ImplItemFn,
 ) -> Result<TokenStream, syn::Error> {
@@ -263,15 +987,23 @@ fn java_method_fn(
         ref_type,
         args,
         output,
-    } = parse_fn(path, ty, input)?;
-    let java_fn_name = fn_name.to_string().to_case(Case::Camel);
-    let java_fn_name = format!("{java_fn_name}Extern");
-    let java_ident = Ident::new(&java_fn_name, Span::call_site()).to_token_stream();
-    let j_output = match output {
-        syn::ReturnType::Type(_, ty) => {
+        throws,
+    } = parse_fn(ty, input)?;
+    let path = class.qualified_name();
+    let package_path = class.package.replace('.', "/");
+    if class.sync == SyncStrategy::None && matches!(ref_type, Some(RefType::Mut)) {
+        return Err(syn::Error::new(
+            input.sig.span(),
+            "`&mut self` needs a lock, which `sync = \"none\"` doesn't have. Use \"rwlock\", \"mutex\" or \"thread_confined\" instead",
+        ));
+    }
+    let java_ident = Ident::new(&method.native_name, Span::call_site()).to_token_stream();
+    let j_output = match &output {
+        Some(ty) => {
+            let ty = static_lifetimes(ty)?;
             quote! {<#ty as JType>::JType<'local>}
         }
-        x => x.to_token_stream(),
+        None => quote! {()},
     };
 
     let j_args: Vec<TokenStream> = args
@@ -279,51 +1011,277 @@ fn java_method_fn(
         .map(|PatType { pat, ty, .. }| {
             let j_arg = format!("j_{}", pat.into_token_stream());
             let j_arg = Ident::new(&j_arg, Span::call_site()).to_token_stream();
-            quote! {#j_arg: <#ty as JType>::JType<'local>}
+            if ref_arg_type(ty).is_some() {
+                quote! {#j_arg: jni::objects::JObject<'local>}
+            } else if is_str_arg(ty) {
+                quote! {#j_arg: jni::objects::JString<'local>}
+            } else if let Some((_, elem)) = slice_arg_type(ty) {
+                quote! {#j_arg: jni::objects::JPrimitiveArray<'local, <#elem as SliceElement>::Jni>}
+            } else {
+                quote! {#j_arg: <#ty as JType>::JType<'local>}
+            }
         })
         .collect();
-    let to_rust_types: Vec<TokenStream> = args.iter().map(|PatType { pat, ty , ..}| {
-                let rest_arg = pat.to_token_stream();
+    let to_rust_types: Vec<TokenStream> = args
+        .iter()
+        .filter(|PatType { ty, .. }| {
+            ref_arg_type(ty).is_none() && slice_arg_type(ty).is_none() && !is_str_arg(ty)
+        })
+        .map(|PatType { pat, ty, .. }| {
+            let rest_arg = pat.to_token_stream();
             let j_arg = format!("j_{}", pat.into_token_stream());
             let j_arg = Ident::new(&j_arg, Span::call_site()).to_token_stream();
-                 quote! {
-                    let #rest_arg: #ty  = PrimitiveJType::from_j_type(&mut env, #j_arg).expect("Failed to cast");
-                }
-
-    }).collect();
+            // a buffer argument only borrows the java buffer for the call, so it isn't a
+            // `PrimitiveJType` that could outlive it
+            let inner = option_type(ty);
+            let from_j_type = match inner.unwrap_or(ty) {
+                buffer if is_direct_buffer(&buffer.to_token_stream().to_string()) => match inner {
+                    Some(_) => quote! {jni_cli_core::DirectBuffer::from_nullable},
+                    None => quote! {jni_cli_core::DirectBuffer::from_j_type},
+                },
+                _ => quote! {PrimitiveJType::from_j_type},
+            };
+            quote! {
+                let #rest_arg: #ty  = #from_j_type(&mut env, #j_arg)?;
+            }
+        })
+        .collect();
+    // objects passed by reference are reserved before converting the arguments passed by
+    // value, which would otherwise free them if the same object is passed twice
+    let ref_handles: TokenStream = ref_type
+        .as_ref()
+        .filter(|ref_type| !matches!(ref_type, RefType::Owned))
+        .map(|_| {
+            quote! {
+                let handle = <#ty as JavaClass>::handle_from_object(&mut env, &this)?;
+                let _handle_reserved = jni_cli_core::reserve(handle);
+            }
+        })
+        .into_iter()
+        .chain(args.iter().filter_map(|PatType { pat, ty, .. }| {
+            let (_, elem) = ref_arg_type(ty)?;
+            let j_arg = Ident::new(&format!("j_{}", pat.to_token_stream()), Span::call_site());
+            let handle = Ident::new(
+                &format!("{}_handle", pat.to_token_stream()),
+                Span::call_site(),
+            );
+            let reserved = Ident::new(
+                &format!("_{}_reserved", pat.to_token_stream()),
+                Span::call_site(),
+            );
+            Some(quote! {
+                let #handle = <#elem as JavaClass>::handle_from_object(&mut env, &#j_arg)?;
+                let #reserved = jni_cli_core::reserve(#handle);
+            })
+        }))
+        .collect();
 
     let j_args: Punctuated<TokenStream, Comma> = once(quote! {mut env: jni::JNIEnv<'local>})
         .chain(once(quote! {class: jni::objects::JClass<'local>}))
-        .chain(ref_type.as_ref().map(|_| quote! {handle: jni::sys::jlong}))
+        .chain(
+            ref_type
+                .as_ref()
+                .map(|_| quote! {this: jni::objects::JObject<'local>}),
+        )
         .chain(j_args)
         .collect();
     let call_args: Punctuated<TokenStream, Comma> = args
-        .into_iter()
+        .iter()
         .map(|PatType { pat, .. }| pat.to_token_stream())
         .collect();
-    let transforms: TokenStream = to_rust_types.into_iter().collect();
-    let fn_call = ref_type
-        .map(|ref_type| match ref_type {
-            RefType::Shared => quote! {
-                <#ty as JavaClass>::use_shared(handle, |self_type| self_type.#fn_name(#call_args))
-            },
+    // taken after reserving the arguments passed by reference, so that `self` can't also be
+    // borrowed by the same call
+    let take_self = ref_type
+        .as_ref()
+        .filter(|ref_type| matches!(ref_type, RefType::Owned))
+        .map(|_| quote! {let self_type = <#ty as JavaClass>::take_from_object(&mut env, &this)?;});
+    // strings passed as `&str` or `Cow<str>` are borrowed rather than copied
+    let str_args = args.iter().filter(|PatType { ty, .. }| is_str_arg(ty)).map(
+        |PatType { pat, ty, .. }| {
+            let ident = |prefix: &str, suffix: &str| {
+                Ident::new(
+                    &format!("{prefix}{}{suffix}", pat.to_token_stream()),
+                    Span::call_site(),
+                )
+            };
+            let (j_arg, chars, cow) = (ident("j_", ""), ident("", "_chars"), ident("", "_cow"));
+            let to_rust = match ty.as_ref() {
+                Type::Reference(_) => quote! {
+                    let #cow = #chars.to_cow();
+                    let #pat: &str = &#cow;
+                },
+                _ => quote! {
+                    let #pat = #chars.to_cow();
+                },
+            };
+            quote! {
+                let #chars = jni_cli_core::StrArg::new(&env, &#j_arg)?;
+                #to_rust
+            }
+        },
+    );
+    let transforms: TokenStream = take_self
+        .into_iter()
+        .chain(to_rust_types)
+        .chain(str_args)
+        .collect();
+    let exception_class = format!("{package_path}/{RUST_EXCEPTION}");
+    let panic_class = format!("{package_path}/{RUST_PANIC_EXCEPTION}");
+    let plain_call = match ref_type {
+        Some(_) => quote! {self_type.#fn_name(#call_args)},
+        None => quote! {#ty::#fn_name(#call_args)},
+    };
+    // slices borrow the java arrays innermost, after every lock is taken, as no other JNI call
+    // may happen and no other thread may be waited on until they're released at the end of the
+    // call. Their lengths are taken and aliasing is checked up front for the same reason
+    let slice_args: Vec<_> = args
+        .iter()
+        .filter_map(|PatType { pat, ty, .. }| {
+            let (ref_type, elem) = slice_arg_type(ty)?;
+            let ident = |suffix: &str| {
+                Ident::new(
+                    &format!("{}_{suffix}", pat.to_token_stream()),
+                    Span::call_site(),
+                )
+            };
+            let j_arg = Ident::new(&format!("j_{}", pat.to_token_stream()), Span::call_site());
+            Some((pat, ref_type, elem, j_arg, ident("len"), ident("elements")))
+        })
+        .collect();
+    let slice_lengths: TokenStream = slice_args
+        .iter()
+        .map(|(_, _, elem, j_arg, len, _)| {
+            quote! {
+                let #len = jni_cli_core::CriticalArray::<#elem>::length(&mut env, &#j_arg)?;
+            }
+        })
+        .collect();
+    // a `&mut [T]` must not alias any other slice of the same call
+    let distinct_slices: TokenStream = slice_args
+        .iter()
+        .enumerate()
+        .flat_map(|(i, a)| slice_args[i + 1..].iter().map(move |b| (a, b)))
+        .filter(|((_, a_ref, ..), (_, b_ref, ..))| {
+            matches!(a_ref, RefType::Mut) || matches!(b_ref, RefType::Mut)
+        })
+        .map(|((_, _, _, a, ..), (_, _, _, b, ..))| {
+            quote! {
+                jni_cli_core::distinct_arrays(&mut env, &#a, &#b)?;
+            }
+        })
+        .collect();
+    let slices: TokenStream = slice_args
+        .iter()
+        .map(|(pat, ref_type, elem, j_arg, len, elements)| match ref_type {
             RefType::Mut => quote! {
-                <#ty as JavaClass>::use_mut(handle, |self_type| self_type.#fn_name(#call_args))
+                let mut #elements = unsafe { jni_cli_core::CriticalArray::<#elem>::new(&env, &#j_arg, #len, true)? };
+                let #pat = #elements.as_mut_slice();
+            },
+            _ => quote! {
+                let #elements = unsafe { jni_cli_core::CriticalArray::<#elem>::new(&env, &#j_arg, #len, false)? };
+                let #pat = #elements.as_slice();
             },
         })
-        .unwrap_or_else(|| quote! {#ty::#fn_name(#call_args)});
+        .collect();
 
+    let with_slices = |call: TokenStream| {
+        if slice_args.is_empty() {
+            call
+        } else {
+            quote! {{
+                #slices
+                #call
+            }}
+        }
+    };
+    let fn_call = if throws {
+        quote! {
+            #plain_call.map_err(|err| jni_cli_core::Error::exception(#exception_class, err))
+        }
+    } else {
+        quote! {std::result::Result::<_, jni_cli_core::Error>::Ok(#plain_call)}
+    };
+    let fn_call = with_slices(fn_call);
+    // borrow the objects passed by reference, innermost first
+    let fn_call = args
+        .iter()
+        .rev()
+        .filter_map(|PatType { pat, ty, .. }| Some((pat, ref_arg_type(ty)?)))
+        .fold(fn_call, |fn_call, (pat, (ref_type, elem))| {
+            let handle = Ident::new(
+                &format!("{}_handle", pat.to_token_stream()),
+                Span::call_site(),
+            );
+            match ref_type {
+                RefType::Shared => quote! {
+                    <#elem as JavaClass>::use_shared(#handle, |#pat| #fn_call)
+                },
+                RefType::Mut => quote! {
+                    <#elem as JavaClass>::use_mut(#handle, |#pat| #fn_call)
+                },
+                RefType::Owned => unreachable!("arguments taken by value aren't borrowed"),
+            }
+        });
+    // the generated class keeps the object alive for as long as a returned buffer, which is
+    // created and counted before `self` is released so that it can't be freed in between
+    let (fn_call, to_j_type) = match output.as_ref().map(|ty| ty.to_token_stream().to_string()) {
+        Some(output) if is_direct_buffer(&output) => (
+            quote! {
+                #fn_call.and_then(|output| jni_cli_core::buffer_returned(&mut env, &this, output))
+            },
+            quote! {Ok(output)},
+        ),
+        Some(output) if option_inner(&output).is_some_and(|inner| is_direct_buffer(&inner)) => (
+            quote! {
+                #fn_call.and_then(|output| match output {
+                    Some(output) => jni_cli_core::buffer_returned(&mut env, &this, output),
+                    None => Ok(jni_cli_core::JNull::null()),
+                })
+            },
+            quote! {Ok(output)},
+        ),
+        _ => (fn_call, quote! {output.to_j_type(&mut env)}),
+    };
+    let fn_call = match ref_type {
+        Some(RefType::Shared) => quote! {
+            <#ty as JavaClass>::use_shared(handle, |self_type| #fn_call)?
+        },
+        Some(RefType::Mut) => quote! {
+            <#ty as JavaClass>::use_mut(handle, |self_type| #fn_call)?
+        },
+        Some(RefType::Owned) | None
+            if !throws
+                && args
+                    .iter()
+                    .all(|PatType { ty, .. }| ref_arg_type(ty).is_none()) =>
+        {
+            with_slices(plain_call)
+        }
+        Some(RefType::Owned) | None => quote! {#fn_call?},
+    };
+    // the binding of a method left out by `#[cfg]` would call a method that doesn't exist
+    let cfgs = input
+        .attrs
+        .iter()
+        .filter(|attr| attr.path().is_ident("cfg") || attr.path().is_ident("cfg_attr"));
     Ok(quote! {
+        #(#cfgs)*
         #[jni_fn(#path)]
         pub fn #java_ident<'local>(#j_args) -> #j_output {
-            #transforms
-            #fn_call.to_j_type(&mut env).expect("Failed to cast")
+            let result = jni_cli_core::catch_unwind(#panic_class, || -> std::result::Result<#j_output, jni_cli_core::Error> {
+                #ref_handles
+                #transforms
+                #slice_lengths
+                #distinct_slices
+                let output = #fn_call;
+                #to_j_type
+            });
+            jni_cli_core::unwrap_or_throw(&mut env, result)
         }
     })
 }
 
 pub fn java_class_fn(attr: TokenStream, item: TokenStream) -> Result<TokenStream, syn::Error> {
-    let attr_span = attr.span();
     let item_span = item.span();
     let impl_name: syn::ItemImpl = match syn::parse2(item.clone()) {
         Ok(s) => s,
@@ -334,25 +1292,21 @@ pub fn java_class_fn(attr: TokenStream, item: TokenStream) -> Result<TokenStream
             ))
         }
     };
-    let struct_name = impl_name.self_ty.to_token_stream();
-
-    let namespace = match syn::parse2::<syn::LitStr>(attr) {
-        Ok(n) => n,
-        Err(_e) => return Err(syn::Error::new(attr_span, "The `java_class` attribute must have a single string literal supplied to specify the class path")),
-    }.value();
+    let struct_n = impl_name.self_ty.to_token_stream();
+    // the macro can't see the module it's expanded in, only the cli knows the full path
+    let class = class_manifest(struct_n.to_string(), syn::parse2(attr)?, &impl_name)?;
+    let storage = class.sync.storage();
+    let returns_buffers = returns_buffers(&class).then(|| {
+        quote! {const RETURNS_BUFFERS: bool = true;}
+    });
 
-    let namespace = format!("{namespace}.{struct_name}");
-    let namepath = namespace.replace('.', "/");
-    let struct_n = struct_name;
+    let panic_class = format!("{}/{RUST_PANIC_EXCEPTION}", class.package.replace('.', "/"));
+    let namespace = class.qualified_name();
+    let namepath = class.jni_name();
 
-    let fns: TokenStream = impl_name
-        .items
-        .iter()
-        .filter_map(|impl_item| match impl_item {
-            ImplItem::Fn(fn_item) => Some(fn_item),
-            _ => None,
-        })
-        .map(|fn_item| java_method_fn(&namespace, &struct_n, fn_item))
+    let fns: TokenStream = impl_fns(&impl_name)
+        .zip(&class.methods)
+        .map(|(fn_item, method)| java_method_fn(&class, method, &struct_n, fn_item))
         .collect::<Result<_, syn::Error>>()?;
 
     Ok(quote! {
@@ -363,151 +1317,294 @@ pub fn java_class_fn(attr: TokenStream, item: TokenStream) -> Result<TokenStream
         unsafe impl JavaClass for #struct_n {
             const LOC: &'static str = #namespace;
             const PATH: &'static str = #namepath;
+            type Storage = #storage;
+            #returns_buffers
         }
         #fns
 
         #[jni_fn(#namespace)]
-        pub fn dropByHandleExtern<'local>(env: jni::JNIEnv<'local>, _class: jni::objects::JClass<'local>, handle: jni::sys::jlong) {}
+        pub fn dropByHandleExtern<'local>(mut env: jni::JNIEnv<'local>, _class: jni::objects::JClass<'local>, handle: jni::sys::jlong) -> jni::sys::jboolean {
+            let result = jni_cli_core::catch_unwind(#panic_class, || {
+                let dropped = unsafe { <#struct_n as JavaClass>::drop_by_handle(handle) };
+                Ok(dropped.into())
+            });
+            jni_cli_core::unwrap_or_throw(&mut env, result)
+        }
     })
 }
 
-fn map_jni_type(ident: Ident) -> Result<&'static str, syn::Error> {
-    let ret = match ident.to_string().as_ref() {
-        "jboolean" => "Boolean",
-        "jbyte" => "Byte",
-        "jshort" => "Short",
-        "jint" => "Int",
-        "jlong" => "Long",
-        "jfloat" => "Float",
-        "jdouble" => "Double",
-        "JString" => "String",
-        "JBooleanArray" => "BooleanArray",
-        "JByteArray" => "ByteArray",
-        "JShortArray" => "ShortArray",
-        "JIntArray" => "IntArray",
-        "JLongArray" => "LongArray",
-        "JFloatArray" => "FloutArray",
-        "JDoubleArray" => "DoubleArray",
-        "JObjectArray" => "ObjectArray",
-        _ => return Err(syn::Error::new(Span::call_site(), "Shouldn't be here")),
-    };
-    Ok(ret)
+/// The conversions of a fieldless `#[java_enum]`, from its variant to the constant of the same
+/// name and back from the constant's ordinal
+fn enum_class_conversions(enum_item: &Enum) -> (TokenStream, TokenStream) {
+    let idents: Vec<_> = enum_item
+        .variants
+        .iter()
+        .map(|variant| format_ident!("{}", variant.rust_name))
+        .collect();
+    let names = enum_item.variants.iter().map(|variant| &variant.name);
+    let ordinals = 0..idents.len();
+    let len = idents.len();
+    let to_j_type = quote! {
+        match self {
+            #(Self::#idents => <Self as jni_cli_core::JavaEnum>::constant(env, #names),)*
+        }
+    };
+    let from_j_type = quote! {
+        match <Self as jni_cli_core::JavaEnum>::ordinal(env, &j_type, #len)? {
+            #(#ordinals => Ok(Self::#idents),)*
+            _ => unreachable!(),
+        }
+    };
+    (to_j_type, from_j_type)
 }
 
-fn map_kotlin_type_from_rust(ident: &str) -> Result<&'static str, syn::Error> {
-    let ret = match ident.to_string().as_ref() {
-        "bool" => "Boolean",
-        "i8" => "Byte",
-        "i16" => "Short",
-        "i32" => "Int",
-        "i64" => "Long",
-        "f32" => "Float",
-        "f63" => "Double",
-        "String" => "String",
-        "Vec < bool >" => "BooleanArray",
-        "Vec < i8 >" => "ByteArray",
-        "Vec < u8 >" => "ByteArray",
-        "Vec < i16 >" => "ShortArray",
-        "Vec < i32 >" => "IntArray",
-        "Vec < i64 >" => "LongArray",
-        "Vec < f32 >" => "FloutArray",
-        "Vec < f64 >" => "DoubleArray",
-        "Vec < String >" => "Array<String>",
-        x => {
-            return Err(syn::Error::new(
-                Span::call_site(),
-                format!("Shouldn't be here: {x}: result {}", x == "Vec < u8 >"),
-            ))
+/// The conversions of a `#[java_enum]` with fields, from its variant to an instance of the
+/// variant's subclass of the sealed class and back
+fn sealed_class_conversions(enum_item: &Enum, item: &ItemEnum) -> (TokenStream, TokenStream) {
+    let mut to_j_type = vec![];
+    let mut from_j_type = vec![];
+    for (index, (variant, item_variant)) in
+        enum_item.variants.iter().zip(&item.variants).enumerate()
+    {
+        let ident = &item_variant.ident;
+        let name = &variant.name;
+        let members: Vec<_> = item_variant.fields.members().collect();
+        let tys: Vec<_> = item_variant.fields.iter().map(|field| &field.ty).collect();
+        let names = variant.fields.iter().map(|field| &field.name);
+        let bindings: Vec<_> = (0..members.len())
+            .map(|index| format_ident!("field{index}"))
+            .collect();
+        to_j_type.push(quote! {
+            Self::#ident { #(#members: #bindings),* } => {
+                let fields = [#((
+                    <#tys as jni_cli_core::JField>::DESCRIPTOR,
+                    <#tys as jni_cli_core::JField>::into_value(jni_cli_core::JType::to_j_type(#bindings, env)?),
+                )),*];
+                <Self as jni_cli_core::JavaEnum>::new_variant(env, #name, &fields)
+            }
+        });
+        from_j_type.push(quote! {
+            #index => Ok(Self::#ident {
+                #(#members: <Self as jni_cli_core::JavaEnum>::field::<#tys>(env, &j_type, #names)?),*
+            }),
+        });
+    }
+    let names = enum_item.variants.iter().map(|variant| &variant.name);
+    let to_j_type = quote! {
+        match self {
+            #(#to_j_type)*
         }
     };
-    Ok(ret)
-}
-fn map_jni_type_from_rust(ident: Ident) -> Result<&'static str, syn::Error> {
-    let ret = match ident.to_string().as_ref() {
-        "bool" => "Boolean",
-        "i8" => "Byte",
-        "i16" => "Short",
-        "i32" => "Int",
-        "i64" => "Long",
-        "f32" => "Float",
-        "f63" => "Double",
-        "String" => "String",
-        "Vec < bool >" => "BooleanArray",
-        "Vec < i8 >" => "ByteArray",
-        "Vec < u8 >" => "ByteArray",
-        "Vec < i16 >" => "ShortArray",
-        "Vec < i32 >" => "IntArray",
-        "Vec < i64 >" => "LongArray",
-        "Vec < f32 >" => "FloutArray",
-        "Vec < f64 >" => "DoubleArray",
-        "Vec < String >" => "Array<String>",
-        _ => return Err(syn::Error::new(Span::call_site(), "Shouldn't be here")),
-    };
-    Ok(ret)
+    let from_j_type = quote! {
+        match <Self as jni_cli_core::JavaEnum>::variant_of(env, &j_type, &[#(#names),*])? {
+            #(#from_j_type)*
+            _ => unreachable!(),
+        }
+    };
+    (to_j_type, from_j_type)
+}
+
+pub fn java_enum_fn(attr: TokenStream, item: TokenStream) -> Result<TokenStream, syn::Error> {
+    let enum_item: ItemEnum = syn::parse2(item.clone()).map_err(|_| {
+        syn::Error::new(
+            item.span(),
+            "The `java_enum` attribute can only be applied to enums",
+        )
+    })?;
+    let ident = &enum_item.ident;
+    // as with `#[java_class]`, only the cli knows the full path
+    let manifest = enum_manifest(ident.to_string(), syn::parse2(attr)?, &enum_item)?;
+    let namespace = manifest.qualified_name();
+    let namepath = manifest.jni_name();
+    let descriptor = format!("L{namepath};");
+    let (to_j_type, from_j_type) = if manifest.is_fieldless() {
+        enum_class_conversions(&manifest)
+    } else {
+        sealed_class_conversions(&manifest, &enum_item)
+    };
+
+    Ok(quote! {
+
+        #item
+
+        impl jni_cli_core::JavaEnum for #ident {
+            const LOC: &'static str = #namespace;
+            const PATH: &'static str = #namepath;
+        }
+
+        impl jni_cli_core::JType for #ident {
+            type JType<'a> = jni::objects::JObject<'a>;
+
+            fn to_j_type<'env>(
+                self,
+                env: &mut jni::JNIEnv<'env>,
+            ) -> std::result::Result<Self::JType<'env>, jni_cli_core::Error> {
+                #to_j_type
+            }
+        }
+
+        impl jni_cli_core::PrimitiveJType for #ident {
+            fn from_j_type<'env>(
+                env: &mut jni::JNIEnv<'env>,
+                j_type: Self::JType<'env>,
+            ) -> std::result::Result<Self, jni_cli_core::Error> {
+                #from_j_type
+            }
+        }
+
+        impl jni_cli_core::JNullable for #ident {
+            type Nullable<'a> = jni::objects::JObject<'a>;
+
+            fn to_nullable<'env>(
+                value: Option<Self>,
+                env: &mut jni::JNIEnv<'env>,
+            ) -> std::result::Result<Self::Nullable<'env>, jni_cli_core::Error> {
+                match value {
+                    Some(value) => jni_cli_core::JType::to_j_type(value, env),
+                    None => Ok(jni::objects::JObject::null()),
+                }
+            }
+        }
+
+        impl jni_cli_core::PrimitiveJNullable for #ident {
+            fn from_nullable<'env>(
+                env: &mut jni::JNIEnv<'env>,
+                j_type: Self::Nullable<'env>,
+            ) -> std::result::Result<Option<Self>, jni_cli_core::Error> {
+                if j_type.is_null() {
+                    return Ok(None);
+                }
+                jni_cli_core::PrimitiveJType::from_j_type(env, j_type).map(Some)
+            }
+        }
+
+        impl jni_cli_core::JField for #ident {
+            const DESCRIPTOR: &'static str = #descriptor;
+
+            fn into_value(j_type: Self::JType<'_>) -> jni::objects::JValueOwned<'_> {
+                jni::objects::JValueOwned::Object(j_type)
+            }
+
+            fn from_value(
+                value: jni::objects::JValueOwned<'_>,
+            ) -> std::result::Result<Self::JType<'_>, jni_cli_core::Error> {
+                Ok(value.l()?)
+            }
+        }
+    })
+}
+
+/// The kotlin type of a rust type that isn't a `#[java_class]` or `#[java_enum]`, from the same
+/// tables as the `JType` impls
+fn map_kotlin_type_from_rust(ident: &str) -> Result<&'static str, syn::Error> {
+    match ident {
+        "" | "()" => return Ok("Unit"),
+        "String" => return Ok("String"),
+        // borrowed by `&str` and `Cow<str>`
+        "str" => return Ok("String"),
+        "Vec < String >" => return Ok("Array<String>"),
+        _ if is_cow_str(ident) => return Ok("String"),
+        _ if is_direct_buffer(ident) => return Ok(DIRECT_BUFFER_CLASS),
+        _ => {}
+    }
+    if let Some(primitive) = PRIMITIVES.iter().find(|p| p.rust == ident) {
+        return Ok(primitive.kotlin);
+    }
+    // slices are borrowed from the same arrays a `Vec` is copied from
+    let element = ident
+        .strip_prefix("Vec < ")
+        .and_then(|rest| rest.strip_suffix(" >"))
+        .or_else(|| ident.strip_prefix('[')?.strip_suffix(']'));
+    if let Some(array) = PRIMITIVE_ARRAYS.iter().find(|a| Some(a.element) == element) {
+        return Ok(array.kotlin);
+    }
+    Err(syn::Error::new(
+        Span::call_site(),
+        format!("{ident} can't be passed to the JVM, it isn't a primitive, String, Vec of those, DirectBuffer, #[java_class] or #[java_enum]"),
+    ))
 }
 
 fn kotlin_class_fn(
-    _class_name: &str,
     j_fn_name: &str,
     j_args_with_types: &str,
-    j_args: &str,
-    output: &str,
+    call: &str,
     output_class: &str,
     cleanup: bool,
+    throws: bool,
 ) -> String {
     format!(
         r#"
-    fun {j_fn_name}({j_args_with_types}): {output_class} {{
-        val obj = Companion.{j_fn_name}Extern({j_args})
+    {throws}fun {j_fn_name}({j_args_with_types}): {output_class} {{
+        val obj = {call}
         {cleanup}
         return obj
     }}
         "#,
+        throws = kotlin_throws(throws),
         cleanup = if cleanup {
-            kotlin_cleanup(&format!("{output_class}.Companion"), output)
+            kotlin_cleanup(output_class)
         } else {
             String::new()
         }
     )
 }
 
-fn kotlin_private_native_fn(j_fn_name: &str, j_args_with_types: &str, output: &str) -> String {
+fn kotlin_private_native_fn(
+    native_name: &str,
+    j_args_with_types: &str,
+    output: &str,
+    throws: bool,
+) -> String {
     format!(
         r#"
         @JvmStatic
-        private external fun {j_fn_name}Extern({j_args_with_types}): {output}
-        "#
+        {throws}private external fun {native_name}({j_args_with_types}): {output}
+        "#,
+        throws = kotlin_throws(throws),
     )
 }
 
-fn kotlin_cleanup(class_path: &str, class_name: &str) -> String {
+fn kotlin_throws(throws: bool) -> String {
+    if throws {
+        format!("@Throws({RUST_EXCEPTION}::class)\n        ")
+    } else {
+        String::new()
+    }
+}
+
+fn kotlin_cleanup(output_class: &str) -> String {
+    let safe_call = if output_class.ends_with('?') {
+        "?."
+    } else {
+        "."
+    };
     format!(
         r#"
-        CLEANER.register(obj, {class_path}.{class_name}Cleaner(obj.handle));
+        obj{safe_call}registerCleaner()
     "#
     )
 }
 
 fn kotlin_static_fn(
-    class_name: &str,
     j_fn_name: &str,
     j_args_with_types: &str,
-    j_args: &str,
+    call: &str,
     output_class: &str,
-    _output: &str,
     cleanup: bool,
+    throws: bool,
 ) -> String {
     format!(
         r#"
         @JvmStatic
-        fun {j_fn_name}({j_args_with_types}): {output_class} {{
-            val obj = {j_fn_name}Extern({j_args})
+        {throws}fun {j_fn_name}({j_args_with_types}): {output_class} {{
+            val obj = {call}
             {cleanup}
             return obj
         }}
         "#,
+        throws = kotlin_throws(throws),
         cleanup = if cleanup {
-            kotlin_cleanup("Companion", class_name)
+            kotlin_cleanup(output_class)
         } else {
             String::new()
         }
@@ -520,14 +1617,79 @@ pub struct KotlinClass {
     pub code: String,
 }
 
-fn kotlin_class(
-    project_root: &str,
-    path: &str,
-    kotlin_static_fns: &str,
-    kotlin_fns: &str,
-    class_name: &str,
-    rust_lib: &str,
-) -> KotlinClass {
+fn kotlin_class(
+    project_root: &str,
+    path: &str,
+    kotlin_static_fns: &str,
+    kotlin_fns: &str,
+    class_name: &str,
+    rust_lib: &str,
+    returns_buffers: bool,
+) -> KotlinClass {
+    // a returned ByteBuffer points into the rust object, which is only freed once they're all
+    // unreachable
+    let (buffer_fields, close, buffer_fns) = if returns_buffers {
+        (
+            r#"
+    /** Returned ByteBuffers over memory of this object that are still reachable, read by rust */
+    private var buffers = 0
+    /** Whether [close] waits for the last reachable ByteBuffer to free the rust object */
+    private var closePending = false"#,
+            r#"
+    /**
+     * Frees the rust object now rather than when this object is garbage collected, or once the
+     * last ByteBuffer it returned is unreachable. A call still running on another thread leaves
+     * it to the garbage collector. Any later method call throws an [IllegalStateException].
+     */
+    override fun close() {
+        handle = 0
+        synchronized(this) {
+            if (buffers > 0) {
+                closePending = true
+                return
+            }
+        }
+        cleaner?.close()
+    }"#,
+            r#"
+
+    /**
+     * Called by rust with the ByteBuffer it created over this object, while the method returning
+     * it still borrows the object. Views of it like `asReadOnlyBuffer()` or `slice()` keep that
+     * buffer reachable, so this object is kept alive until it's unreachable
+     */
+    @Synchronized
+    private fun bufferReturned(buffer: java.nio.ByteBuffer) {
+        buffers++
+        CLEANER.register(buffer) { bufferReleased() }
+    }
+
+    /** Runs once a returned ByteBuffer is unreachable, this object is kept alive until then */
+    private fun bufferReleased() {
+        synchronized(this) {
+            buffers--
+            if (buffers > 0 || !closePending) {
+                return
+            }
+        }
+        cleaner?.close()
+    }"#,
+        )
+    } else {
+        (
+            "",
+            r#"
+    /**
+     * Frees the rust object now rather than when this object is garbage collected, unless a call
+     * still runs on another thread. Any later method call throws an [IllegalStateException].
+     */
+    override fun close() {
+        handle = 0
+        cleaner?.close()
+    }"#,
+            "",
+        )
+    };
     KotlinClass {
         path: path.into(),
         name: class_name.into(),
@@ -536,24 +1698,61 @@ fn kotlin_class(
 package {path}
 
 import {project_root}.Library.CLEANER
-import cz.adamh.utils.NativeUtils
-
-class {class_name} {{
-    private var handle: Long = -1
+import {project_root}.NativeLoader
+import java.lang.ref.Cleaner
+import java.util.concurrent.atomic.AtomicLong
+
+class {class_name} : AutoCloseable {{
+    @Volatile
+    internal var handle: Long = 0
+    private var cleaner: {class_name}Cleaner? = null
+    private var cleanable: Cleaner.Cleanable? = null{buffer_fields}
     companion object {{
-        val _libImport = NativeUtils.loadLibraryFromJar("/lib{rust_lib}.dylib")
+        val _libImport = NativeLoader.load("{rust_lib}")
+
+        class {class_name}Cleaner(handle: Long): Runnable {{
+            private val handle = AtomicLong(handle)
+
+            /** Stops the rust object from being dropped, once rust has taken ownership of it */
+            fun disarm() {{
+                handle.set(0)
+            }}
 
-        class {class_name}Cleaner(val handle: Long): Runnable {{
+            /**
+             * Drops the rust object, unless a call on another thread still uses it. It's then
+             * left to the garbage collector, which can only run this once that call returned
+             */
+            fun close() {{
+                val handle = handle.getAndSet(0)
+                if (handle != 0L && !dropByHandleExtern(handle)) {{
+                    this.handle.set(handle)
+                }}
+            }}
 
             override fun run() {{
-                dropByHandleExtern(handle)
+                close()
             }}
         }}
         {kotlin_static_fns}
 
         @JvmStatic
-        private external fun dropByHandleExtern(handle: Long)
+        private external fun dropByHandleExtern(handle: Long): Boolean
+    }}
+{close}
+
+    internal fun registerCleaner() {{
+        val cleaner = {class_name}Cleaner(handle)
+        this.cleaner = cleaner
+        cleanable = CLEANER.register(this, cleaner)
     }}
+
+    /** Called after passing this object by value, rust zeroes the handle when it takes ownership */
+    internal fun releaseIfConsumed() {{
+        if (handle == 0L) {{
+            cleaner?.disarm()
+            cleanable?.clean()
+        }}
+    }}{buffer_fns}
     {kotlin_fns}
 }}
 
@@ -562,119 +1761,323 @@ class {class_name} {{
     }
 }
 
-use syn::visit::{self, Visit};
-struct ImplVisitor {
-    impls: Vec<ItemImpl>,
+/// The exceptions thrown by generated methods in package `path`, when the rust method
+/// returned an `Err` or panicked
+pub fn exception_classes(path: &str) -> Vec<KotlinClass> {
+    vec![
+        KotlinClass {
+            path: path.into(),
+            name: RUST_EXCEPTION.into(),
+            code: format!(
+                r#"
+package {path}
+
+class {RUST_EXCEPTION}(message: String) : Exception(message)
+    "#
+            ),
+        },
+        KotlinClass {
+            path: path.into(),
+            name: RUST_PANIC_EXCEPTION.into(),
+            code: format!(
+                r#"
+package {path}
+
+class {RUST_PANIC_EXCEPTION}(message: String) : RuntimeException(message)
+    "#
+            ),
+        },
+    ]
+}
+
+/// The `Library` object in `project_root` holding the `Cleaner` shared by all classes
+pub fn library_object(project_root: &str) -> KotlinClass {
+    KotlinClass {
+        path: project_root.into(),
+        name: "Library".into(),
+        code: format!(
+            r#"
+package {project_root}
+
+object Library {{
+    val CLEANER =
+        java.lang.ref.Cleaner.create()
+}}
+"#
+        ),
+    }
+}
+
+/// The `NativeLoader` object in `project_root`, loading the native library bundled for the
+/// running platform out of `natives/<os>-<arch>/`. The bundled platforms are listed one per
+/// line in the `natives/platforms` resource.
+pub fn native_loader(project_root: &str) -> KotlinClass {
+    let env_prefix = project_root.to_uppercase().replace(['.', '-'], "_");
+    KotlinClass {
+        path: project_root.into(),
+        name: "NativeLoader".into(),
+        code: format!(
+            r#"
+package {project_root}
+
+import java.io.File
+import java.io.IOException
+import java.nio.file.Files
+import java.nio.file.Path
+import java.nio.file.Paths
+import java.nio.file.StandardCopyOption
+import java.security.MessageDigest
+
+/**
+ * Loads the rust library bundled under `natives/<os>-<arch>/`, extracting it to a directory named
+ * after its sha-256 so that the extraction of an earlier run is reused.
+ *
+ * For development, point [LIBRARY_PATH_PROPERTY] or [LIBRARY_PATH_ENV] at a locally built library
+ * to load that instead. Platforms without a bundled library fall back to `System.loadLibrary`.
+ */
+object NativeLoader {{
+    const val LIBRARY_PATH_PROPERTY = "{project_root}.library.path"
+    const val LIBRARY_PATH_ENV = "{env_prefix}_LIBRARY_PATH"
+    /** Directory to extract the library to, defaults to a directory in `java.io.tmpdir` */
+    const val CACHE_DIR_PROPERTY = "{project_root}.cache.dir"
+
+    /** The `<os>-<arch>` directories under `natives/` the library was built for */
+    val SUPPORTED_PLATFORMS: List<String> by lazy {{
+        NativeLoader::class.java
+            .getResourceAsStream("/natives/platforms")
+            ?.use {{ it.reader().readLines().filter(String::isNotBlank) }}
+            ?: emptyList()
+    }}
+
+    /** The platform of the running JVM, named like the directories under `natives/` */
+    val PLATFORM: String by lazy {{
+        val osName = System.getProperty("os.name").lowercase()
+        val os = when {{
+            osName.startsWith("linux") -> "linux"
+            osName.startsWith("mac") || osName.startsWith("darwin") -> "macos"
+            osName.startsWith("windows") -> "windows"
+            else -> osName.replace(' ', '_')
+        }}
+        val arch = when (val arch = System.getProperty("os.arch").lowercase()) {{
+            "amd64", "x86_64", "x64" -> "x86_64"
+            "aarch64", "arm64" -> "aarch64"
+            "x86", "i386", "i486", "i586", "i686" -> "x86"
+            else -> arch
+        }}
+        "$os-$arch"
+    }}
+
+    private val loaded = mutableSetOf<String>()
+
+    /** Loads the native library `name`, once */
+    @Synchronized
+    fun load(name: String) {{
+        if (name in loaded) {{
+            return
+        }}
+        val override = System.getProperty(LIBRARY_PATH_PROPERTY) ?: System.getenv(LIBRARY_PATH_ENV)
+        if (override != null) {{
+            System.load(File(override).absolutePath)
+        }} else {{
+            val fileName = System.mapLibraryName(name)
+            val bytes = NativeLoader::class.java
+                .getResourceAsStream("/natives/$PLATFORM/$fileName")
+                ?.use {{ it.readBytes() }}
+            if (bytes != null) {{
+                System.load(extract(bytes, fileName).toString())
+            }} else {{
+                try {{
+                    System.loadLibrary(name)
+                }} catch (e: UnsatisfiedLinkError) {{
+                    throw UnsatisfiedLinkError(
+                        "$name was not built for $PLATFORM, only for ${{SUPPORTED_PLATFORMS.joinToString()}}, " +
+                            "and isn't on java.library.path either: ${{e.message}}"
+                    )
+                }}
+            }}
+        }}
+        loaded.add(name)
+    }}
+
+    private fun extract(bytes: ByteArray, fileName: String): Path {{
+        val hash = MessageDigest.getInstance("SHA-256")
+            .digest(bytes)
+            .joinToString("") {{ "%02x".format(it) }}
+        val cacheDir = System.getProperty(CACHE_DIR_PROPERTY)?.let {{ Paths.get(it) }}
+            ?: Paths.get(System.getProperty("java.io.tmpdir"), "{project_root}-natives")
+        val dir = cacheDir.resolve(hash)
+        val library = dir.resolve(fileName)
+        if (Files.isRegularFile(library) && Files.size(library) == bytes.size.toLong()) {{
+            return library
+        }}
+        Files.createDirectories(dir)
+        // written next to the library and moved in place, so no other JVM sees a partial file
+        val partial = Files.createTempFile(dir, fileName, ".partial")
+        try {{
+            Files.write(partial, bytes)
+            Files.move(partial, library, StandardCopyOption.ATOMIC_MOVE)
+        }} catch (e: IOException) {{
+            // another JVM may have extracted it first
+            if (!Files.isRegularFile(library) || Files.size(library) != bytes.size.toLong()) {{
+                throw e
+            }}
+        }} finally {{
+            Files.deleteIfExists(partial)
+        }}
+        return library
+    }}
+}}
+"#
+        ),
+    }
+}
+
+/// The manifests of the `#[java_class]` impl blocks of the module, with their types resolved
+pub fn module_classes(module: &RustModule, lookup: &PackageLookup) -> Result<Vec<Class>, Error> {
+    let types = TypeResolver::new(module, lookup);
+    module
+        .java_class_impls()?
+        .into_iter()
+        .map(|(impl_item, attr)| {
+            let mut class = class_manifest(types.rust_path(&impl_item.self_ty)?, attr, impl_item)?;
+            let self_ty = impl_item.self_ty.to_token_stream().to_string();
+            resolve_types(&mut class, &self_ty, &types)?;
+            Ok(class)
+        })
+        .collect()
+}
+
+/// The manifests of the `#[java_enum]`s of the module, with their types resolved
+pub fn module_enums(module: &RustModule, lookup: &PackageLookup) -> Result<Vec<Enum>, Error> {
+    let types = TypeResolver::new(module, lookup);
+    module
+        .java_enums()?
+        .into_iter()
+        .map(|(enum_item, attr)| {
+            let mut manifest = enum_manifest(types.enum_path(enum_item), attr, enum_item)?;
+            resolve_enum_types(&mut manifest, &types)?;
+            Ok(manifest)
+        })
+        .collect()
+}
+
+/// The kotlin `enum class` of a fieldless `#[java_enum]`, or else its `sealed class` with a
+/// `data class` for every variant with fields and a `data object` for the others
+pub fn enum_to_kotlin(enum_item: &Enum) -> Result<KotlinClass, Error> {
+    let Enum { package, name, .. } = enum_item;
+    let mut body = String::new();
+    if enum_item.is_fieldless() {
+        for variant in &enum_item.variants {
+            body.push_str(&format!("    {},\n", variant.name));
+        }
+        body = format!("enum class {name} {{\n{body}}}");
+    } else {
+        for variant in &enum_item.variants {
+            let variant_name = &variant.name;
+            if variant.fields.is_empty() {
+                body.push_str(&format!("    data object {variant_name} : {name}()\n"));
+                continue;
+            }
+            let fields = variant
+                .fields
+                .iter()
+                .map(|field| Ok(format!("val {}: {}", field.name, kotlin_type(&field.ty)?)))
+                .collect::<Result<Vec<_>, Error>>()?
+                .join(", ");
+            body.push_str(&format!(
+                "    data class {variant_name}({fields}) : {name}()\n"
+            ));
+        }
+        body = format!("sealed class {name} {{\n{body}}}");
+    }
+    Ok(KotlinClass {
+        path: package.clone(),
+        name: name.clone(),
+        code: format!(
+            r#"
+package {package}
+
+{body}
+"#
+        ),
+    })
 }
-impl<'ast> Visit<'ast> for ImplVisitor {
-    fn visit_item_impl(&mut self, node: &'ast ItemImpl) {
-        self.impls.push(node.clone());
-        visit::visit_item_impl(self, node);
+
+/// The kotlin class wrapping the native methods of a resolved `#[java_class]`
+pub fn class_to_kotlin(
+    project_root: &str,
+    class: &Class,
+    rust_lib: &str,
+) -> Result<KotlinClass, Error> {
+    let mut companion_fns = String::new();
+    let mut class_fns = String::new();
+    for method in &class.methods {
+        companion_fns.push('\n');
+        companion_fns.push_str(&kotlin_companion_method(class, method)?);
+        if let Some(class_fn) = kotlin_class_method(method)? {
+            class_fns.push('\n');
+            class_fns.push_str(&class_fn);
+        }
     }
+    Ok(kotlin_class(
+        project_root,
+        &class.package,
+        &companion_fns,
+        &class_fns,
+        &class.name,
+        rust_lib,
+        returns_buffers(class),
+    ))
 }
 
-pub fn rust_file_to_tokens(
+pub fn rust_module_to_tokens(
     project_root: &str,
-    rust_file_str: &str,
+    module: &RustModule,
     lookup: &PackageLookup,
     rust_lib: &str,
 ) -> Result<Vec<KotlinClass>, Error> {
-    let syntax_tree: File = syn::parse_str(rust_file_str)?;
-    let mut visitor = ImplVisitor { impls: Vec::new() };
-    visitor.visit_file(&syntax_tree);
-    visitor
-        .impls
+    module_classes(module, lookup)?
         .iter()
-        .flat_map(|impl_item| {
-            impl_item
-                .attrs
-                .iter()
-                .filter_map(|attr| match &attr.meta {
-                    Meta::List(MetaList { path, tokens, .. })
-                        if path
-                            .get_ident()
-                            .map(|ident| ident.to_string().as_str() == "java_class")
-                            .unwrap_or(false) =>
-                    {
-                        Some(
-                            syn::parse2::<LitStr>(tokens.clone())
-                                .map(|res| res.value().to_string()),
-                        )
-                    }
-                    _ => None,
-                })
-                .collect::<Vec<_>>()
-                .into_iter()
-                .map(|namespace| -> Result<_, _> {
-                    let namespace = namespace?;
-                    let struct_n = impl_item.self_ty.to_token_stream();
-                    let _path = format!("{namespace}.{struct_n}");
-
-                    let companion_fns = impl_item
-                        .items
-                        .iter()
-                        .filter_map(|impl_item| match impl_item {
-                            ImplItem::Fn(fn_item) => Some(fn_item),
-                            _ => None,
-                        })
-                        .filter_map(|fn_item| {
-                            match kotlin_companion_method(&namespace, &struct_n, fn_item, lookup) {
-                                Ok(Some(x)) => Some(Ok(x)),
-                                Err(err) => Some(Err(err)),
-                                Ok(None) => None,
-                            }
-                        })
-                        .try_fold::<_, _, Result<String, syn::Error>>(
-                            String::new(),
-                            |mut accum, line| {
-                                accum.push('\n');
-                                accum.push_str(&(line?));
-                                Ok(accum)
-                            },
-                        )?;
-                    let class_fns = impl_item
-                        .items
-                        .iter()
-                        .filter_map(|impl_item| match impl_item {
-                            ImplItem::Fn(fn_item) => Some(fn_item),
-                            _ => None,
-                        })
-                        .filter_map(|fn_item| {
-                            match kotlin_class_method(&namespace, &struct_n, fn_item, lookup) {
-                                Ok(Some(x)) => Some(Ok(x)),
-                                Err(err) => Some(Err(err)),
-                                Ok(None) => None,
-                            }
-                        })
-                        .try_fold::<_, _, Result<String, syn::Error>>(
-                            String::new(),
-                            |mut accum, line| {
-                                accum.push('\n');
-                                accum.push_str(&(line?));
-                                Ok(accum)
-                            },
-                        )?;
-                    Ok(kotlin_class(
-                        project_root,
-                        &namespace,
-                        &companion_fns,
-                        &class_fns,
-                        struct_n.to_string().as_str(),
-                        rust_lib,
-                    ))
-                })
-        })
-        .collect::<Result<Vec<_>, _>>()
+        .map(|class| class_to_kotlin(project_root, class, rust_lib))
+        .collect()
 }
 
 #[cfg(test)]
 mod test {
-    use crate::token_processing::rust_file_to_tokens;
-
-    use super::{java_class_fn, KotlinClass, PackageLookup};
+    use super::{
+        enum_to_kotlin, fill_lookup, java_class_fn, java_enum_fn, map_kotlin_type_from_rust,
+        module_classes, module_enums, native_loader, rust_module_to_tokens, KotlinClass,
+        PackageLookup, RustModule,
+    };
+    use crate::manifest::{RefType, SyncStrategy};
+    use crate::{PRIMITIVES, PRIMITIVE_ARRAYS};
 
+    use proc_macro2::TokenStream;
     use quote::quote;
 
+    /// The kotlin class and the macro expansion of an impl block of `SomeStruct` with
+    /// `#[java_class(#attr)]`, which can take `beep.tkz.Tokenizer`s
+    fn expand(attr: TokenStream, item: TokenStream) -> (String, String) {
+        let stream = quote! {
+            #[java_class(#attr)]
+            #item
+        };
+        let lookup = PackageLookup {
+            classes: [
+                ("crate::SomeStruct".into(), "beep.bop.SomeStruct".into()),
+                ("crate::Tokenizer".into(), "beep.tkz.Tokenizer".into()),
+            ]
+            .into_iter()
+            .collect(),
+            ..Default::default()
+        };
+        let module = RustModule::parse("crate", &stream.to_string()).expect("valid rust");
+        let mut classes =
+            rust_module_to_tokens("beep.boop", &module, &lookup, "lets_go").expect("Not OK");
+        let tokens = java_class_fn(attr, item).expect("Okay").to_string();
+        (classes.remove(0).code, tokens)
+    }
+
     #[test]
     fn test_java_method() {
         let attr = quote! {"boop.bop"};
@@ -725,17 +2128,600 @@ mod test {
                 };
 
         let token_str = stream.to_string();
-        let lookup: PackageLookup = [("SomeStruct".into(), "beep.bop.SomeStruct".into())]
-            .into_iter()
-            .collect();
+        let lookup = PackageLookup {
+            classes: [("crate::SomeStruct".into(), "beep.bop.SomeStruct".into())]
+                .into_iter()
+                .collect(),
+            ..Default::default()
+        };
 
         let rust_lib = "lets_go";
 
+        let module = RustModule::parse("crate", &token_str).expect("valid rust");
         let tokens =
-            rust_file_to_tokens("beep.boop", &token_str, &lookup, rust_lib).expect("Not OK");
+            rust_module_to_tokens("beep.boop", &module, &lookup, rust_lib).expect("Not OK");
         for KotlinClass { code: token, .. } in tokens {
             println!("Tokens: {token}");
+            assert!(token.contains("class SomeStruct : AutoCloseable"));
+            assert!(token.contains("obj.registerCleaner()"));
+        }
+    }
+
+    #[test]
+    fn test_result_throws() {
+        let (code, tokens) = expand(
+            quote! {"beep.bop"},
+            quote! {
+                impl SomeStruct {
+                    fn try_new(string: String) -> Result<SomeStruct, std::io::Error> {
+                        todo!()
+                    }
+                    fn try_stuff(&self, string: String) -> Result<i32, String> {
+                        todo!()
+                    }
+                }
+            },
+        );
+        assert_eq!(code.matches("@Throws(RustException::class)").count(), 4);
+        assert!(code.contains("fun tryStuff(string: String): Int"));
+        assert!(tokens.contains("\"beep/bop/RustException\""));
+    }
+
+    #[test]
+    fn test_native_loader() {
+        let loader = native_loader("beep.boop");
+        assert_eq!(loader.path, "beep.boop");
+        assert!(loader
+            .code
+            .contains(r#".getResourceAsStream("/natives/platforms")"#));
+        assert!(loader
+            .code
+            .contains(r#"const val LIBRARY_PATH_PROPERTY = "beep.boop.library.path""#));
+        assert!(loader
+            .code
+            .contains(r#"const val LIBRARY_PATH_ENV = "BEEP_BOOP_LIBRARY_PATH""#));
+        assert!(loader.code.contains(r#""/natives/$PLATFORM/$fileName""#));
+        assert!(!loader.code.contains("NativeUtils"));
+    }
+
+    #[test]
+    fn test_sync_strategy() {
+        let impl_item = quote! {
+            impl SomeStruct {
+                fn len(&self) -> i64 {
+                    todo!()
+                }
+            }
+        };
+        let tokens = java_class_fn(quote! {"beep.bop"}, impl_item.clone())
+            .expect("Okay")
+            .to_string();
+        assert!(tokens.contains("type Storage = std :: sync :: RwLock < Self >"));
+
+        let tokens = java_class_fn(quote! {"beep.bop", sync = "thread_confined"}, impl_item)
+            .expect("Okay")
+            .to_string();
+        assert!(
+            tokens.contains("type Storage = jni_cli_core :: storage :: ThreadConfined < Self >")
+        );
+
+        let impl_item = quote! {
+            impl SomeStruct {
+                fn clear(&mut self) {
+                    todo!()
+                }
+            }
+        };
+        assert!(java_class_fn(quote! {"beep.bop", sync = "none"}, impl_item.clone()).is_err());
+        assert!(java_class_fn(quote! {"beep.bop", sync = "spinlock"}, impl_item).is_err());
+
+        let stream = quote! {
+            #[java_class("beep.bop", sync = "mutex")]
+            impl SomeStruct {}
+        };
+        let mut lookup = PackageLookup::default();
+        let module = RustModule::parse("crate", &stream.to_string()).expect("valid rust");
+        fill_lookup(&module, &mut lookup).expect("Okay");
+        assert_eq!(lookup.classes["crate::SomeStruct"], "beep.bop.SomeStruct");
+        let classes =
+            rust_module_to_tokens("beep.boop", &module, &lookup, "lets_go").expect("Not OK");
+        assert_eq!(classes[0].path, "beep.bop");
+    }
+
+    #[test]
+    fn test_consuming_self() {
+        let (code, tokens) = expand(
+            quote! {"beep.bop"},
+            quote! {
+                impl SomeStruct {
+                    fn with_padding(self, padding: i32) -> Self {
+                        todo!()
+                    }
+                }
+            },
+        );
+        assert!(code.contains("fun withPadding(padding: Int): beep.bop.SomeStruct"));
+        assert!(code.contains("} finally {\n            releaseIfConsumed()"));
+        assert!(code.contains("@Volatile\n    internal var handle: Long = 0"));
+        assert!(code.contains("if (handle != 0L && !dropByHandleExtern(handle)) {"));
+        assert!(tokens.contains(
+            "let self_type = < SomeStruct as JavaClass > :: take_from_object (& mut env , & this) ?"
+        ));
+        assert!(!tokens.contains("use_shared"));
+    }
+
+    #[test]
+    fn test_object_args() {
+        let (code, tokens) = expand(
+            quote! {"beep.bop"},
+            quote! {
+                impl SomeStruct {
+                    fn merge(&mut self, other: &Self, tokenizer: &mut Tokenizer) -> i32 {
+                        todo!()
+                    }
+                    fn absorb(&self, other: SomeStruct, maybe: Option<Tokenizer>) {
+                        todo!()
+                    }
+                }
+            },
+        );
+        assert!(code
+            .contains("fun merge(other: beep.bop.SomeStruct, tokenizer: beep.tkz.Tokenizer): Int"));
+        assert!(code.contains("Companion.mergeExtern(this, other, tokenizer)"));
+        assert!(code.contains("other.releaseIfConsumed()\n            maybe?.releaseIfConsumed()"));
+        assert!(!code.contains("tokenizer.releaseIfConsumed()"));
+        assert!(tokens.contains("j_other : jni :: objects :: JObject < 'local >"));
+        assert!(tokens.contains("< SomeStruct as JavaClass > :: use_shared (other_handle"));
+        assert!(tokens.contains("< Tokenizer as JavaClass > :: use_mut (tokenizer_handle"));
+    }
+
+    #[test]
+    fn test_option_nullable() {
+        let (code, _) = expand(
+            quote! {"beep.bop"},
+            quote! {
+                impl SomeStruct {
+                    fn maybe_new(idx: Option<i32>) -> Option<SomeStruct> {
+                        todo!()
+                    }
+                    fn find(&self, token: Option<String>) -> Option<i64> {
+                        todo!()
+                    }
+                    fn find_in(&self, token: std::option::Option<String>) -> core::option::Option<i64> {
+                        todo!()
+                    }
+                }
+            },
+        );
+        assert!(code.contains("fun maybeNew(idx: Int?): beep.bop.SomeStruct?"));
+        assert!(code.contains("obj?.registerCleaner()"));
+        assert!(code.contains("fun find(token: String?): Long?"));
+        assert!(code
+            .contains("private external fun findExtern(self: SomeStruct, token: String?): Long?"));
+        assert!(code.contains("fun findIn(token: String?): Long?"));
+    }
+
+    #[test]
+    fn test_slice_args() {
+        let (code, tokens) = expand(
+            quote! {"beep.bop"},
+            quote! {
+                impl SomeStruct {
+                    fn fill(&self, bytes: &[u8], counts: &mut [i32]) -> i64 {
+                        todo!()
+                    }
+                }
+            },
+        );
+        assert!(code.contains("fun fill(bytes: ByteArray, counts: IntArray): Long"));
+        assert!(!code.contains("bytes.releaseIfConsumed()"));
+        assert!(tokens.contains(
+            "j_bytes : jni :: objects :: JPrimitiveArray < 'local , < u8 as SliceElement > :: Jni >"
+        ));
+        assert!(tokens.contains(
+            "jni_cli_core :: CriticalArray :: < u8 > :: new (& env , & j_bytes , bytes_len , false) ?"
+        ));
+        assert!(tokens.contains("let counts = counts_elements . as_mut_slice () ;"));
+        assert!(!tokens.contains("counts_handle"));
+        assert!(tokens
+            .contains("jni_cli_core :: distinct_arrays (& mut env , & j_bytes , & j_counts) ?"));
+        // the lock is taken before the arrays are pinned
+        let lock = tokens.find("use_shared (handle").expect("borrows self");
+        let pin = tokens
+            .find("CriticalArray :: < u8 > :: new")
+            .expect("pins bytes");
+        assert!(lock < pin);
+    }
+
+    #[test]
+    fn test_str_args() {
+        let (code, tokens) = expand(
+            quote! {"beep.bop"},
+            quote! {
+                impl SomeStruct {
+                    fn tokenize(&self, text: &str, pair: std::borrow::Cow<'_, str>) -> i64 {
+                        todo!()
+                    }
+                }
+            },
+        );
+        assert!(code.contains("fun tokenize(text: String, pair: String): Long"));
+        assert!(!code.contains("text.releaseIfConsumed()"));
+        assert!(tokens.contains("j_text : jni :: objects :: JString < 'local >"));
+        assert!(
+            tokens.contains("let text_chars = jni_cli_core :: StrArg :: new (& env , & j_text) ?")
+        );
+        assert!(tokens.contains("let text : & str = & text_cow ;"));
+        assert!(tokens.contains("let pair = pair_chars . to_cow () ;"));
+        assert!(!tokens.contains("text_handle"));
+    }
+
+    #[test]
+    fn test_cfg_methods() {
+        let (_, tokens) = expand(
+            quote! {"beep.bop"},
+            quote! {
+                impl SomeStruct {
+                    #[cfg(windows)]
+                    fn only_windows() -> i64 {
+                        todo!()
+                    }
+                }
+            },
+        );
+        assert!(tokens.contains(
+            "# [cfg (windows)] # [jni_fn (\"beep.bop.SomeStruct\")] pub fn onlyWindowsExtern"
+        ));
+    }
+
+    #[test]
+    fn test_direct_buffer() {
+        let (code, tokens) = expand(
+            quote! {"beep.bop", sync = "mutex"},
+            quote! {
+                impl SomeStruct {
+                    fn view(&mut self) -> DirectBuffer<'_> {
+                        todo!()
+                    }
+                    fn fill(buffer: Option<jni_cli_core::DirectBuffer>) -> i32 {
+                        todo!()
+                    }
+                }
+            },
+        );
+        assert!(code.contains("fun view(): java.nio.ByteBuffer"));
+        assert!(code.contains("CLEANER.register(buffer) { bufferReleased() }"));
+        assert!(!code.contains("CLEANER.register(obj)"));
+        assert!(code.contains("private fun bufferReturned(buffer: java.nio.ByteBuffer)"));
+        assert!(code.contains("closePending = true"));
+        assert!(code.contains("fun fill(buffer: java.nio.ByteBuffer?): Int"));
+        assert!(tokens.contains("-> < DirectBuffer < 'static > as JType > :: JType < 'local >"));
+        assert!(tokens.contains("jni_cli_core :: buffer_returned (& mut env , & this , output)"));
+        assert!(tokens.contains("const RETURNS_BUFFERS : bool = true ;"));
+        assert!(tokens.contains(
+            "let buffer : Option < jni_cli_core :: DirectBuffer > = jni_cli_core :: DirectBuffer :: from_nullable (& mut env , j_buffer) ?"
+        ));
+
+        let attr = quote! {"beep.bop"};
+        let impl_item = quote! {
+            impl SomeStruct {
+                fn view() -> DirectBuffer<'static> {
+                    todo!()
+                }
+            }
+        };
+        assert!(java_class_fn(attr, impl_item).is_err());
+    }
+
+    #[test]
+    fn test_manifest() {
+        let stream = quote! {
+            #[java_class("beep.bop", sync = "mutex")]
+            impl SomeStruct {
+                fn from_parts(first_part: Option<i32>, other: &SomeStruct) -> Result<Self, String> {
+                    todo!()
+                }
+                fn names(&mut self) -> Vec<String> {
+                    todo!()
+                }
+            }
+        };
+        let module = RustModule::parse("crate", &stream.to_string()).expect("valid rust");
+        let mut lookup = PackageLookup::default();
+        fill_lookup(&module, &mut lookup).expect("Okay");
+        let classes = module_classes(&module, &lookup).expect("Not OK");
+        let class = &classes[0];
+        assert_eq!(class.rust_path, "crate::SomeStruct");
+        assert_eq!(class.jni_name(), "beep/bop/SomeStruct");
+        assert_eq!(class.sync, SyncStrategy::Mutex);
+
+        let from_parts = &class.methods[0];
+        assert_eq!(from_parts.name, "fromParts");
+        assert_eq!(from_parts.native_name, "fromPartsExtern");
+        assert_eq!(from_parts.receiver, None);
+        assert!(from_parts.throws);
+        assert_eq!(from_parts.params[0].name, "firstPart");
+        assert!(from_parts.params[0].ty.nullable);
+        assert_eq!(from_parts.params[1].ty.borrow, Some(RefType::Shared));
+        assert!(from_parts.params[1].ty.object);
+        assert_eq!(
+            from_parts.descriptor.as_deref(),
+            Some("(Ljava/lang/Integer;Lbeep/bop/SomeStruct;)Lbeep/bop/SomeStruct;")
+        );
+        let names = &class.methods[1];
+        assert_eq!(names.receiver, Some(RefType::Mut));
+        assert_eq!(
+            names.descriptor.as_deref(),
+            Some("(Lbeep/bop/SomeStruct;)[Ljava/lang/String;")
+        );
+
+        // the macro exports the native methods the manifest names
+        let item = quote! {
+            impl SomeStruct {
+                fn from_parts(first_part: Option<i32>, other: &SomeStruct) -> Result<Self, String> {
+                    todo!()
+                }
+            }
+        };
+        let tokens = java_class_fn(quote! {"beep.bop"}, item)
+            .expect("Okay")
+            .to_string();
+        assert!(tokens.contains("pub fn fromPartsExtern"));
+    }
+
+    #[test]
+    fn test_resolve_rust_paths() {
+        let module = |path: &str, stream: proc_macro2::TokenStream| {
+            RustModule::parse(path, &stream.to_string()).expect("valid rust")
+        };
+        let a = module(
+            "crate::a",
+            quote! {
+                #[java_class("beep.a")]
+                impl Config<'static> {
+                    fn merge(&self, other: &self::Config, same: crate::a::Config, b: super::b::Config) {}
+                }
+            },
+        );
+        let b = module(
+            "crate::b",
+            quote! {
+                #[java_class("beep.b")]
+                impl crate::b::Config {}
+            },
+        );
+        let c = module(
+            "crate::c",
+            quote! {
+                use crate::b::{self, Config as BConfig};
+                use super::a::*;
+
+                #[java_class("beep.c")]
+                impl Holder {
+                    fn new(a: Config, b: BConfig, other_b: b::Config) -> Holder { todo!() }
+                }
+            },
+        );
+        let mut lookup = PackageLookup::default();
+        for module in [&a, &b, &c] {
+            fill_lookup(module, &mut lookup).expect("Okay");
+        }
+        assert_eq!(lookup.classes["crate::a::Config"], "beep.a.Config");
+        assert_eq!(lookup.classes["crate::b::Config"], "beep.b.Config");
+        assert_eq!(lookup.classes["crate::c::Holder"], "beep.c.Holder");
+
+        let classes = rust_module_to_tokens("beep", &a, &lookup, "lets_go").expect("Not OK");
+        assert_eq!(classes[0].name, "Config");
+        assert!(classes[0]
+            .code
+            .contains("fun merge(other: beep.a.Config, same: beep.a.Config, b: beep.b.Config)"));
+        let classes = rust_module_to_tokens("beep", &c, &lookup, "lets_go").expect("Not OK");
+        assert!(classes[0].code.contains(
+            "fun new(a: beep.a.Config, b: beep.b.Config, otherB: beep.b.Config): beep.c.Holder"
+        ));
+
+        let duplicate = module(
+            "crate::d",
+            quote! {
+                #[java_class("beep.a")]
+                impl Config {}
+            },
+        );
+        assert!(fill_lookup(&duplicate, &mut lookup).is_err());
+    }
+
+    #[test]
+    fn test_java_enum() {
+        let stream = quote! {
+            #[java_enum("beep.bop")]
+            enum PaddingDirection {
+                Left,
+                RightToLeft,
+            }
+
+            #[java_enum("beep.bop")]
+            enum Truncation {
+                LongestFirst { max_length: i64, direction: Option<PaddingDirection> },
+                Fixed(i32),
+                Pair(String, Vec<u8>),
+                Off,
+            }
+
+            #[java_class("beep.bop")]
+            impl SomeStruct {
+                fn pad(&self, direction: PaddingDirection, truncation: Option<Truncation>) -> Truncation {
+                    todo!()
+                }
+            }
+        };
+        let module = RustModule::parse("crate", &stream.to_string()).expect("valid rust");
+        let mut lookup = PackageLookup::default();
+        fill_lookup(&module, &mut lookup).expect("Okay");
+        assert_eq!(lookup.enums["crate::Truncation"], "beep.bop.Truncation");
+        assert!(!lookup.classes.contains_key("crate::Truncation"));
+
+        let enums = module_enums(&module, &lookup).expect("Not OK");
+        let (padding, truncation) = (&enums[0], &enums[1]);
+        assert!(padding.is_fieldless());
+        assert_eq!(padding.variants[1].name, "RIGHT_TO_LEFT");
+        assert!(!truncation.is_fieldless());
+        let longest_first = &truncation.variants[0];
+        assert_eq!(longest_first.name, "LongestFirst");
+        assert_eq!(longest_first.fields[0].name, "maxLength");
+        assert_eq!(
+            longest_first.fields[1].ty.descriptor.as_deref(),
+            Some("Lbeep/bop/PaddingDirection;")
+        );
+        assert!(truncation.variants[1].tuple);
+        assert_eq!(truncation.variants[1].fields[0].name, "value");
+        assert_eq!(truncation.variants[2].fields[1].name, "value1");
+
+        let code = enum_to_kotlin(padding).expect("Not OK").code;
+        assert!(code.contains("enum class PaddingDirection {\n    LEFT,\n    RIGHT_TO_LEFT,\n}"));
+        let code = enum_to_kotlin(truncation).expect("Not OK").code;
+        assert!(code.contains("sealed class Truncation {"));
+        assert!(code.contains(
+            "    data class LongestFirst(val maxLength: Long, val direction: beep.bop.PaddingDirection?) : Truncation()"
+        ));
+        assert!(code.contains("    data class Fixed(val value: Int) : Truncation()"));
+        assert!(code.contains(
+            "    data class Pair(val value0: String, val value1: ByteArray) : Truncation()"
+        ));
+        assert!(code.contains("    data object Off : Truncation()"));
+
+        // enums are passed by value, without the cleaners of `#[java_class]` objects
+        let classes = module_classes(&module, &lookup).expect("Not OK");
+        let pad = &classes[0].methods[0];
+        assert!(!pad.params[0].ty.object);
+        assert_eq!(
+            pad.descriptor.as_deref(),
+            Some("(Lbeep/bop/SomeStruct;Lbeep/bop/PaddingDirection;Lbeep/bop/Truncation;)Lbeep/bop/Truncation;")
+        );
+        let classes = rust_module_to_tokens("beep", &module, &lookup, "lets_go").expect("Not OK");
+        assert!(classes[0].code.contains(
+            "fun pad(direction: beep.bop.PaddingDirection, truncation: beep.bop.Truncation?): beep.bop.Truncation"
+        ));
+        assert!(!classes[0].code.contains("obj.registerCleaner()"));
+
+        let tokens = java_enum_fn(
+            quote! {"beep.bop"},
+            quote! {
+                enum PaddingDirection {
+                    Left,
+                    RightToLeft,
+                }
+            },
+        )
+        .expect("Okay")
+        .to_string();
+        assert!(tokens.contains(
+            "Self :: RightToLeft => < Self as jni_cli_core :: JavaEnum > :: constant (env , \"RIGHT_TO_LEFT\")"
+        ));
+        assert!(tokens.contains(
+            "match < Self as jni_cli_core :: JavaEnum > :: ordinal (env , & j_type , 2usize) ? { 0usize => Ok (Self :: Left) , 1usize => Ok (Self :: RightToLeft) ,"
+        ));
+        assert!(
+            tokens.contains("const DESCRIPTOR : & 'static str = \"Lbeep/bop/PaddingDirection;\"")
+        );
+
+        let tokens = java_enum_fn(
+            quote! {"beep.bop"},
+            quote! {
+                enum Truncation {
+                    LongestFirst { max_length: i64 },
+                    Fixed(i32),
+                    Off,
+                }
+            },
+        )
+        .expect("Okay")
+        .to_string();
+        assert!(tokens.contains("Self :: LongestFirst { max_length : field0 } =>"));
+        assert!(tokens.contains(
+            "< i64 as jni_cli_core :: JField > :: into_value (jni_cli_core :: JType :: to_j_type (field0 , env) ?)"
+        ));
+        assert!(tokens.contains(
+            "< Self as jni_cli_core :: JavaEnum > :: new_variant (env , \"Fixed\" , & fields)"
+        ));
+        assert!(tokens.contains(
+            "variant_of (env , & j_type , & [\"LongestFirst\" , \"Fixed\" , \"Off\"]) ?"
+        ));
+        assert!(tokens.contains(
+            "1usize => Ok (Self :: Fixed { 0 : < Self as jni_cli_core :: JavaEnum > :: field :: < i32 > (env , & j_type , \"value\") ? }) ,"
+        ));
+        assert!(tokens.contains("2usize => Ok (Self :: Off { }) ,"));
+
+        let with_object = RustModule::parse(
+            "crate",
+            &quote! {
+                #[java_enum("beep.bop")]
+                enum Holder {
+                    Some(SomeStruct),
+                }
+            }
+            .to_string(),
+        )
+        .expect("valid rust");
+        let mut lookup = PackageLookup {
+            classes: [("crate::SomeStruct".into(), "beep.bop.SomeStruct".into())]
+                .into_iter()
+                .collect(),
+            ..Default::default()
+        };
+        fill_lookup(&with_object, &mut lookup).expect("Okay");
+        assert!(module_enums(&with_object, &lookup).is_err());
+        let duplicate = RustModule::parse(
+            "crate::other",
+            &quote! {
+                #[java_enum("beep.bop")]
+                enum SomeStruct {}
+            }
+            .to_string(),
+        )
+        .expect("valid rust");
+        assert!(fill_lookup(&duplicate, &mut lookup).is_err());
+    }
+
+    #[test]
+    fn test_map_kotlin_types() {
+        assert_eq!(
+            map_kotlin_type_from_rust("f64").expect("primitive"),
+            "Double"
+        );
+        assert_eq!(
+            map_kotlin_type_from_rust("char").expect("primitive"),
+            "Char"
+        );
+        assert_eq!(
+            map_kotlin_type_from_rust("Vec < f32 >").expect("array"),
+            "FloatArray"
+        );
+        assert_eq!(
+            map_kotlin_type_from_rust("Vec < u8 >").expect("array"),
+            "ByteArray"
+        );
+        for primitive in PRIMITIVES {
+            let kotlin = map_kotlin_type_from_rust(primitive.rust).expect("primitive");
+            assert_eq!(kotlin, primitive.kotlin);
+        }
+        assert_eq!(
+            map_kotlin_type_from_rust("[i32]").expect("slice"),
+            "IntArray"
+        );
+        assert_eq!(map_kotlin_type_from_rust("str").expect("str"), "String");
+        assert_eq!(
+            map_kotlin_type_from_rust("std :: borrow :: Cow < 'a , str >").expect("cow"),
+            "String"
+        );
+        for array in PRIMITIVE_ARRAYS {
+            let rust = format!("Vec < {} >", array.element);
+            assert_eq!(
+                map_kotlin_type_from_rust(&rust).expect("array"),
+                array.kotlin
+            );
         }
+        assert!(map_kotlin_type_from_rust("u32").is_err());
+        assert!(map_kotlin_type_from_rust("Vec < u32 >").is_err());
     }
 }
 
@@ -1,12 +1,4 @@
-
-
-
-
-
-
-
-
-use jni_cli_core::token_processing::java_class_fn;
+use jni_cli_core::token_processing::{java_class_fn, java_enum_fn};
 
 #[proc_macro_attribute]
 pub fn java_class(
@@ -14,6 +6,16 @@ pub fn java_class(
     item: proc_macro::TokenStream,
 ) -> proc_macro::TokenStream {
     java_class_fn(attr.into(), item.into())
-        .expect("Oops")
+        .unwrap_or_else(syn::Error::into_compile_error)
+        .into()
+}
+
+#[proc_macro_attribute]
+pub fn java_enum(
+    attr: proc_macro::TokenStream,
+    item: proc_macro::TokenStream,
+) -> proc_macro::TokenStream {
+    java_enum_fn(attr.into(), item.into())
+        .unwrap_or_else(syn::Error::into_compile_error)
         .into()
 }